@@ -0,0 +1,414 @@
+// Generates the ELF fixtures used by the unit tests
+//
+// The fixtures are written field by field rather than linked, so that no
+// binaries are checked in and no cross toolchain is needed to run the tests.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Object file types.
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+// Program header types and flags.
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_NOTE: u32 = 4;
+const PT_GNU_STACK: u32 = 0x6474e551;
+const PT_GNU_RELRO: u32 = 0x6474e552;
+const PT_GNU_PROPERTY: u32 = 0x6474e553;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+// Section header types and flags.
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOTE: u32 = 7;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+
+// Dynamic array tags.
+const DT_NULL: u64 = 0;
+const DT_SYMTAB: u64 = 6;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_SYMENT: u64 = 11;
+
+// x86_64 relocation types.
+const R_X86_64_64: u64 = 1;
+const R_X86_64_RELATIVE: u64 = 8;
+
+// The link address of the executable fixture.
+const BASE: u64 = 0xffffffff80000000;
+
+// The physical load address of the 32-bit fixture.
+const BASE32: u32 = 0x100000;
+
+// The code of both fixtures: a halt loop.
+const CODE: [u8; 16] = [0xf4, 0xeb, 0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+// A little-endian ELF file being written.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.0.push(x);
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn bytes(&mut self, x: &[u8]) {
+        self.0.extend_from_slice(x);
+    }
+
+    // Pad with zeros up to an offset.
+    fn pad_to(&mut self, offset: usize) {
+        assert!(self.0.len() <= offset);
+        self.0.resize(offset, 0);
+    }
+
+    // Write the ELF header, with the program header table right after it.
+    fn header(
+        &mut self,
+        type_: u16,
+        entry: u64,
+        phnum: u16,
+        shoff: u64,
+        shnum: u16,
+        shstrndx: u16,
+    ) {
+        self.bytes(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        self.u16(type_);
+        self.u16(0x3e);
+        self.u32(1);
+        self.u64(entry);
+        self.u64(64);
+        self.u64(shoff);
+        self.u32(0);
+        self.u16(64);
+        self.u16(56);
+        self.u16(phnum);
+        self.u16(64);
+        self.u16(shnum);
+        self.u16(shstrndx);
+    }
+
+    // Write an ELF-32 header, with the program header table right after it.
+    fn header32(&mut self, entry: u32, phnum: u16) {
+        self.bytes(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        self.u16(ET_EXEC);
+        self.u16(0x03);
+        self.u32(1);
+        self.u32(entry);
+        self.u32(52);
+        self.u32(0);
+        self.u32(0);
+        self.u16(52);
+        self.u16(32);
+        self.u16(phnum);
+        self.u16(40);
+        self.u16(0);
+        self.u16(0);
+    }
+
+    // Write an ELF-32 program header table entry, loaded at its virtual address.
+    fn segment32(
+        &mut self,
+        type_: u32,
+        flags: u32,
+        offset: u32,
+        addr: u32,
+        filesz: u32,
+        memsz: u32,
+    ) {
+        self.u32(type_);
+        self.u32(offset);
+        self.u32(addr);
+        self.u32(addr);
+        self.u32(filesz);
+        self.u32(memsz);
+        self.u32(flags);
+        self.u32(0x1000);
+    }
+
+    // Write a program header table entry.
+    #[allow(clippy::too_many_arguments)]
+    fn segment(
+        &mut self,
+        type_: u32,
+        flags: u32,
+        offset: u64,
+        vaddr: u64,
+        filesz: u64,
+        memsz: u64,
+        align: u64,
+    ) {
+        self.u32(type_);
+        self.u32(flags);
+        self.u64(offset);
+        self.u64(vaddr);
+        self.u64(vaddr);
+        self.u64(filesz);
+        self.u64(memsz);
+        self.u64(align);
+    }
+
+    // Write a section header table entry.
+    #[allow(clippy::too_many_arguments)]
+    fn section(
+        &mut self,
+        name: u32,
+        type_: u32,
+        flags: u64,
+        addr: u64,
+        offset: u64,
+        size: u64,
+        link: u32,
+        info: u32,
+        align: u64,
+        entsize: u64,
+    ) {
+        self.u32(name);
+        self.u32(type_);
+        self.u64(flags);
+        self.u64(addr);
+        self.u64(offset);
+        self.u64(size);
+        self.u32(link);
+        self.u32(info);
+        self.u64(align);
+        self.u64(entsize);
+    }
+
+    // Write a symbol table entry for a global function.
+    fn symbol(&mut self, name: u32, shndx: u16, value: u64, size: u64) {
+        self.u32(name);
+        self.u8(1 << 4 | 2);
+        self.u8(0);
+        self.u16(shndx);
+        self.u64(value);
+        self.u64(size);
+    }
+}
+
+// A string table being built.
+struct Strings(Vec<u8>);
+
+impl Strings {
+    fn new() -> Strings {
+        Strings(vec![0])
+    }
+
+    // Add a string, return its offset in the table.
+    fn add(&mut self, string: &str) -> u32 {
+        let offset = self.0.len() as u32;
+        self.0.extend_from_slice(string.as_bytes());
+        self.0.push(0);
+        offset
+    }
+}
+
+// An executable linked in the higher half, with text and data segments, a boot
+// request note, a symbol table and section headers.
+fn executable() -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.header(ET_EXEC, BASE + 0x1000, 3, 0x20a0, 7, 6);
+    w.segment(
+        PT_LOAD,
+        PF_R | PF_X,
+        0x1000,
+        BASE + 0x1000,
+        0x10,
+        0x10,
+        0x1000,
+    );
+    w.segment(
+        PT_LOAD,
+        PF_R | PF_W,
+        0x2000,
+        BASE + 0x2000,
+        0x28,
+        0x1000,
+        0x1000,
+    );
+    w.segment(PT_NOTE, PF_R, 0x2008, BASE + 0x2008, 0x20, 0x20, 4);
+
+    w.pad_to(0x1000);
+    w.bytes(&CODE);
+
+    // The data, followed by a note requesting a 64 KiB stack.
+    w.pad_to(0x2000);
+    w.u64(0x1122334455667788);
+    w.u32(10);
+    w.u32(8);
+    w.u32(2);
+    w.bytes(b"uefi-boot\0\0\0");
+    w.u64(0x10000);
+
+    w.symbol(0, 0, 0, 0);
+    w.symbol(1, 1, BASE + 0x1000, 0x10);
+    w.bytes(b"\0_start\0");
+
+    let mut names = Strings::new();
+    let text = names.add(".text");
+    let data = names.add(".data");
+    let note = names.add(".note.uefi-boot");
+    let symtab = names.add(".symtab");
+    let strtab = names.add(".strtab");
+    let shstrtab = names.add(".shstrtab");
+    w.bytes(&names.0);
+
+    w.pad_to(0x20a0);
+    w.section(0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+    w.section(
+        text,
+        SHT_PROGBITS,
+        SHF_ALLOC | SHF_EXECINSTR,
+        BASE + 0x1000,
+        0x1000,
+        0x10,
+        0,
+        0,
+        16,
+        0,
+    );
+    w.section(
+        data,
+        SHT_PROGBITS,
+        SHF_ALLOC | SHF_WRITE,
+        BASE + 0x2000,
+        0x2000,
+        8,
+        0,
+        0,
+        8,
+        0,
+    );
+    w.section(
+        note,
+        SHT_NOTE,
+        SHF_ALLOC,
+        BASE + 0x2008,
+        0x2008,
+        0x20,
+        0,
+        0,
+        4,
+        0,
+    );
+    w.section(symtab, SHT_SYMTAB, 0, 0, 0x2028, 0x30, 5, 1, 8, 24);
+    w.section(strtab, SHT_STRTAB, 0, 0, 0x2058, 8, 0, 0, 1, 0);
+    w.section(
+        shstrtab,
+        SHT_STRTAB,
+        0,
+        0,
+        0x2060,
+        names.0.len() as u64,
+        0,
+        0,
+        1,
+        0,
+    );
+    w.0
+}
+
+// A position-independent executable linked at zero, with a dynamic array
+// naming two relocations and a dynamic symbol table, and no section headers.
+// Like binaries built by recent toolchains, it has a non-executable stack, a
+// RELRO range and a property note marking it compatible with IBT and SHSTK.
+fn position_independent() -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.header(ET_DYN, 0x1000, 6, 0, 0, 0);
+    w.segment(PT_LOAD, PF_R | PF_X, 0, 0, 0x1030, 0x1030, 0x1000);
+    w.segment(PT_LOAD, PF_R | PF_W, 0x2000, 0x2000, 0x110, 0x110, 0x1000);
+    w.segment(PT_DYNAMIC, PF_R | PF_W, 0x2000, 0x2000, 0x60, 0x60, 8);
+    w.segment(PT_GNU_PROPERTY, PF_R, 0x1010, 0x1010, 0x20, 0x20, 8);
+    w.segment(PT_GNU_STACK, PF_R | PF_W, 0, 0, 0, 0, 16);
+    w.segment(PT_GNU_RELRO, PF_R, 0x2000, 0x2000, 0x100, 0x100, 1);
+
+    w.pad_to(0x1000);
+    w.bytes(&CODE);
+
+    // The property note, with the x86 features IBT and SHSTK.
+    w.u32(4);
+    w.u32(16);
+    w.u32(5);
+    w.bytes(b"GNU\0");
+    w.u32(0xc0000002);
+    w.u32(4);
+    w.u32(3);
+    w.u32(0);
+
+    w.pad_to(0x2000);
+    for (tag, val) in [
+        (DT_RELA, 0x2080),
+        (DT_RELASZ, 48),
+        (DT_RELAENT, 24),
+        (DT_SYMTAB, 0x20b0),
+        (DT_SYMENT, 24),
+        (DT_NULL, 0),
+    ] {
+        w.u64(tag);
+        w.u64(val);
+    }
+
+    // A relative relocation, then one against the symbol.
+    w.pad_to(0x2080);
+    w.u64(0x2100);
+    w.u64(R_X86_64_RELATIVE);
+    w.u64(0x1000);
+    w.u64(0x2108);
+    w.u64(1 << 32 | R_X86_64_64);
+    w.u64(0);
+
+    w.symbol(0, 0, 0, 0);
+    w.symbol(0, 1, 0x1000, 0x10);
+
+    // The relocated words.
+    w.pad_to(0x2110);
+    w.0
+}
+
+// A 32-bit executable linked at 1 MiB, with text and data segments and no
+// section headers.
+fn executable32() -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.header32(BASE32 + 0x1000, 2);
+    w.segment32(PT_LOAD, PF_R | PF_X, 0x1000, BASE32 + 0x1000, 0x10, 0x10);
+    w.segment32(PT_LOAD, PF_R | PF_W, 0x2000, BASE32 + 0x2000, 8, 0x1000);
+
+    w.pad_to(0x1000);
+    w.bytes(&CODE);
+    w.pad_to(0x2000);
+    w.u64(0x1122334455667788);
+    w.0
+}
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+    fs::write(out_dir.join("executable.elf"), executable()).unwrap();
+    fs::write(
+        out_dir.join("position_independent.elf"),
+        position_independent(),
+    )
+    .unwrap();
+    fs::write(out_dir.join("executable32.elf"), executable32()).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,42 @@
+// Fuzz target for parsing an ELF-64 or ELF-32 file and walking its program
+// headers the way the loader does
+
+#![no_main]
+
+use elf64::elf32::Elf32;
+use elf64::{Elf64, PHType};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The loader reads files into page-aligned buffers.
+    let mut words = vec![0u64; data.len().div_ceil(8)];
+    let bytes = unsafe { core::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, data.len()) };
+    bytes.copy_from_slice(data);
+
+    // 32-bit kernels are parsed from the same buffers.
+    if let Ok(elf) = Elf32::from_slice(bytes) {
+        for segment in elf.program_headers().expect("program headers checked by from_slice") {
+            let contents = elf.segment_data(segment).expect("segment checked by from_slice");
+            assert_eq!(contents.len() as u32, segment.filesz);
+        }
+    }
+
+    let elf = match Elf64::from_slice(bytes) {
+        Ok(elf) => elf,
+        Err(_) => return,
+    };
+    let _ = (elf.file_type(), elf.machine(), elf.entry(), elf.is_valid_locally());
+
+    // A parsed file must have a readable program header table and segments.
+    for segment in elf.program_headers().expect("program headers checked by from_slice") {
+        assert!(elf.contains(segment));
+        assert!(segment.memsz >= segment.filesz);
+        let contents = elf.segment_data(segment).expect("segment checked by from_slice");
+        assert_eq!(contents.len() as u64, segment.filesz);
+        if segment.type_() == PHType::Note {
+            for note in elf.segment_notes(segment).unwrap() {
+                let _ = (note.name.len(), note.type_, note.desc.len());
+            }
+        }
+    }
+});
@@ -0,0 +1,181 @@
+//! ELF dynamic linking information
+//!
+//! The dynamic segment holds an array of tagged entries describing, among other
+//! things, the relocations a position-independent program needs to run at its
+//! load address.
+
+use super::Elf64;
+
+/// Possible tags for a dynamic array entry.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DynTag {
+    /// Marks the end of the dynamic array.
+    Null,
+    /// The name of a needed library.
+    Needed,
+    /// The address of the symbol hash table.
+    Hash,
+    /// The address of the string table.
+    StrTab,
+    /// The address of the symbol table.
+    SymTab,
+    /// The address of the relocation table with explicit addends.
+    Rela,
+    /// The total size in bytes of the `Rela` relocation table.
+    RelaSz,
+    /// The size in bytes of a `Rela` relocation table entry.
+    RelaEnt,
+    /// The size in bytes of the string table.
+    StrSz,
+    /// The size in bytes of a symbol table entry.
+    SymEnt,
+    /// The address of the relocation table without explicit addends.
+    Rel,
+    /// The total size in bytes of the `Rel` relocation table.
+    RelSz,
+    /// The size in bytes of a `Rel` relocation table entry.
+    RelEnt,
+    /// Relocations may modify a non-writable segment.
+    TextRel,
+    /// Specified by the operating system / environment.
+    EnvSpecified(i64),
+    /// Specified by the processor type.
+    ProcSpecified(i64),
+    Unknown(i64),
+}
+
+impl From<i64> for DynTag {
+    // Matches an i64 to a dynamic array tag.
+    fn from(x: i64) -> DynTag {
+        match x {
+            0 => DynTag::Null,
+            1 => DynTag::Needed,
+            4 => DynTag::Hash,
+            5 => DynTag::StrTab,
+            6 => DynTag::SymTab,
+            7 => DynTag::Rela,
+            8 => DynTag::RelaSz,
+            9 => DynTag::RelaEnt,
+            10 => DynTag::StrSz,
+            11 => DynTag::SymEnt,
+            17 => DynTag::Rel,
+            18 => DynTag::RelSz,
+            19 => DynTag::RelEnt,
+            22 => DynTag::TextRel,
+            0x6000000d..=0x6ffff000 => DynTag::EnvSpecified(x),
+            0x70000000..=0x7fffffff => DynTag::ProcSpecified(x),
+            _ => DynTag::Unknown(x),
+        }
+    }
+}
+
+/// An ELF-64 dynamic array entry.
+#[repr(C)]
+pub struct Dyn {
+    tag: i64,
+    /// The integer value or address of the entry, depending on the tag.
+    pub val: u64,
+}
+
+impl Dyn {
+    /// Get the tag of a dynamic array entry.
+    pub fn tag(&self) -> DynTag {
+        self.tag.into()
+    }
+}
+
+/// An iterator over the entries of the dynamic array, up to the `Null` entry.
+pub struct DynIter<'a> {
+    _elf: &'a Elf64<'a>,
+    first: *const Dyn,
+    num: usize,
+    current: usize,
+}
+
+impl<'a> DynIter<'a> {
+    /// Create an iterator over the entries of the dynamic array.
+    pub fn from_parts(e: &'a Elf64, f: *const Dyn, n: usize) -> DynIter<'a> {
+        DynIter {
+            _elf: e,
+            first: f,
+            num: n,
+            current: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for DynIter<'a> {
+    type Item = &'a Dyn;
+
+    fn next(&mut self) -> Option<&'a Dyn> {
+        if self.current == self.num {
+            return None;
+        }
+
+        let ptr = self.first as usize + self.current * core::mem::size_of::<Dyn>();
+        let entry = unsafe { &*(ptr as *const Dyn) };
+        if entry.tag() == DynTag::Null {
+            self.current = self.num;
+            None
+        } else {
+            self.current += 1;
+            Some(entry)
+        }
+    }
+}
+
+/// An ELF-64 relocation table entry with an explicit addend.
+#[repr(C)]
+pub struct Rela {
+    /// The virtual address of the location to relocate.
+    pub offset: u64,
+    info: u64,
+    /// The constant addend used to compute the relocated value.
+    pub addend: i64,
+}
+
+impl Rela {
+    /// Get the index of the symbol the relocation refers to.
+    pub fn sym(&self) -> u32 {
+        (self.info >> 32) as u32
+    }
+
+    /// Get the processor-specific relocation type.
+    pub fn type_(&self) -> u32 {
+        self.info as u32
+    }
+}
+
+/// An iterator over the entries of a relocation table.
+pub struct RelaIter<'a> {
+    _elf: &'a Elf64<'a>,
+    first: *const Rela,
+    num: usize,
+    current: usize,
+}
+
+impl<'a> RelaIter<'a> {
+    /// Create an iterator over the entries of a relocation table.
+    pub fn from_parts(e: &'a Elf64, f: *const Rela, n: usize) -> RelaIter<'a> {
+        RelaIter {
+            _elf: e,
+            first: f,
+            num: n,
+            current: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for RelaIter<'a> {
+    type Item = &'a Rela;
+
+    fn next(&mut self) -> Option<&'a Rela> {
+        if self.current == self.num {
+            None
+        } else {
+            let ptr = self.first as usize + self.current * core::mem::size_of::<Rela>();
+            self.current += 1;
+            unsafe { Some(&*(ptr as *const Rela)) }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Definitions for 32-bit ELF files
+//!
+//! Only what is needed to load legacy 32-bit kernels is implemented: the header
+//! and the program header table, checked like their ELF-64 counterparts. Types
+//! shared with ELF-64 are reused.
+
+pub mod program;
+
+use core::mem::size_of;
+use core::result::Result;
+
+pub use super::{ElfAbi, ElfData, ElfType, PHType, SegmentPermissions};
+pub use program::*;
+
+/// A set of errors that may arise.
+#[derive(Debug)]
+pub enum Elf32Error {
+    /// The provided slice is too small (usize holds required size).
+    SliceTooSmall(usize),
+    /// The slice is not an ELF file.
+    NotElf,
+    /// The slice is not an ELF-32 file.
+    NotElf32,
+    /// The version of the ELF file is invalid.
+    InvalidVersion,
+    /// The slice is not aligned for the ELF-32 structures it holds.
+    Misaligned,
+    /// The program header entry size is not the size of a `ProgramHeader`
+    /// (u16 holds the entry size).
+    BadProgramHeaderSize(u16),
+    /// The program header table is misaligned or extends past the end of the
+    /// address space (u32 holds its offset).
+    BadProgramHeaderTable(u32),
+    /// The contents of a segment extend past the end of the file (u32 holds
+    /// the offset of its program header).
+    SegmentOutOfBounds(u32),
+    /// A segment is smaller in memory than in the file, or extends past the end
+    /// of the 32-bit address space (u32 holds the offset of its program header).
+    BadSegmentSize(u32),
+}
+
+/// The possible machine types for an ELF-32 file.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ElfMachine {
+    X86,
+    Unknown(u16),
+}
+
+impl From<u16> for ElfMachine {
+    // Matches a u16 to a machine type.
+    fn from(x: u16) -> ElfMachine {
+        match x {
+            0x03 => ElfMachine::X86,
+            _ => ElfMachine::Unknown(x),
+        }
+    }
+}
+
+// The ELF-32 header.
+#[repr(C)]
+struct Elf32Header {
+    ident: [u8; 16],
+    type_: u16,
+    machine: u16,
+    version: u32,
+    entry: u32,
+    phoff: u32,
+    shoff: u32,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+/// An ELF-32 object file in memory.
+pub struct Elf32<'a>(&'a [u8]);
+
+impl<'a> Elf32<'a> {
+    /// Check if a slice starts with the identification of an ELF-32 file.
+    pub fn is_elf32(slice: &[u8]) -> bool {
+        slice.len() > 4 && slice.starts_with(&[0x7f, 0x45, 0x4C, 0x46]) && slice[4] == 1
+    }
+
+    /// Checks a slice to see if it contains a valid ELF-32 header and program
+    /// header table, and returns an Elf32 structure.
+    pub fn from_slice(slice: &'a [u8]) -> Result<Elf32<'a>, Elf32Error> {
+        // The slice must be long enough to contain an ELF-32 header.
+        let header_size = size_of::<Elf32Header>();
+        if slice.len() < header_size {
+            return Err(Elf32Error::SliceTooSmall(header_size));
+        }
+
+        // The structures of the file are read in place.
+        if !(slice.as_ptr() as usize).is_multiple_of(core::mem::align_of::<Elf32Header>()) {
+            return Err(Elf32Error::Misaligned);
+        }
+
+        // The slice must begin with the ELF magic number.
+        if !slice.starts_with(&[0x7f, 0x45, 0x4C, 0x46]) {
+            return Err(Elf32Error::NotElf);
+        }
+
+        let header: &Elf32Header = unsafe {
+            // Safe because we checked that the slice starts with the ELF magic
+            // number and has a length of at least the size of an ELF-32 header.
+            &*(slice.as_ptr() as *const _ as *const Elf32Header)
+        };
+
+        // ident[4] (class) must equal 1 (32 bit).
+        if header.ident[4] != 1 {
+            return Err(Elf32Error::NotElf32);
+        }
+
+        // ident[6] (version) and version must equal current ELF version (1).
+        if header.ident[6] != 1 || header.version != 1 {
+            return Err(Elf32Error::InvalidVersion);
+        }
+
+        let elf = Elf32(slice);
+        elf.check_segments()?;
+        Ok(elf)
+    }
+
+    // Check that the contents of each segment are in the file, and that each
+    // segment fits in memory.
+    fn check_segments(&self) -> Result<(), Elf32Error> {
+        let entry_size = size_of::<ProgramHeader>() as u32;
+        let entry_offset = |x: usize| self.header().phoff.wrapping_add(x as u32 * entry_size);
+        for (x, segment) in self.program_headers()?.enumerate() {
+            if !self.contains(segment) {
+                return Err(Elf32Error::SegmentOutOfBounds(entry_offset(x)));
+            }
+            if segment.memsz < segment.filesz
+                || segment.vaddr.checked_add(segment.memsz).is_none()
+                || segment.paddr.checked_add(segment.memsz).is_none()
+            {
+                return Err(Elf32Error::BadSegmentSize(entry_offset(x)));
+            }
+        }
+        Ok(())
+    }
+
+    // Get the header from an Elf32 struct.
+    fn header(&self) -> &'a Elf32Header {
+        unsafe {
+            // Safe because &self refers to a valid Elf32 struct, which can
+            // only exist if the slice contains a valid header.
+            &*(self.0.as_ptr() as *const _ as *const Elf32Header)
+        }
+    }
+
+    /// Get the data encoding of the ELF.
+    pub fn data(&self) -> ElfData {
+        self.header().ident[5].into()
+    }
+
+    /// Get the ABI of the ELF.
+    pub fn abi(&self) -> ElfAbi {
+        self.header().ident[7].into()
+    }
+
+    /// Get the ABI version of the ELF.
+    pub fn abi_version(&self) -> u8 {
+        self.header().ident[8]
+    }
+
+    /// Get the file type of the ELF.
+    pub fn file_type(&self) -> ElfType {
+        self.header().type_.into()
+    }
+
+    /// Get the machine type of the ELF.
+    pub fn machine(&self) -> ElfMachine {
+        self.header().machine.into()
+    }
+
+    /// Get the entry point of the ELF.
+    pub fn entry(&self) -> u32 {
+        self.header().entry
+    }
+
+    /// Check if the ELF can run on the current machine in 32-bit mode.
+    pub fn is_valid_locally(&self) -> bool {
+        self.data() == ElfData::LittleEndian && self.machine() == ElfMachine::X86
+    }
+
+    /// Get an iterator over the entries of the program header table.
+    pub fn program_headers(&self) -> Result<ProgramHeaderIter<'_>, Elf32Error> {
+        let header = self.header();
+        if header.phnum == 0 {
+            return Ok(ProgramHeaderIter::from_parts(self, core::ptr::null::<ProgramHeader>(), 0));
+        }
+        if header.phentsize as usize != size_of::<ProgramHeader>() {
+            return Err(Elf32Error::BadProgramHeaderSize(header.phentsize));
+        }
+        if !(header.phoff as usize).is_multiple_of(core::mem::align_of::<ProgramHeader>()) {
+            return Err(Elf32Error::BadProgramHeaderTable(header.phoff));
+        }
+
+        // Check if the slice is long enough to contain the program header table.
+        let phnum = header.phnum as usize;
+        let required_size = super::table_end(header.phoff as u64, phnum, size_of::<ProgramHeader>())
+            .ok_or(Elf32Error::BadProgramHeaderTable(header.phoff))?;
+        if self.0.len() < required_size {
+            return Err(Elf32Error::SliceTooSmall(required_size));
+        }
+
+        let start = self.0.as_ptr() as usize + header.phoff as usize;
+        Ok(ProgramHeaderIter::from_parts(self, start as *const ProgramHeader, header.phnum))
+    }
+
+    /// Check if the contents of a segment are contained in the file.
+    pub fn contains(&self, segment: &ProgramHeader) -> bool {
+        segment.file_end() <= self.0.len() as u64
+    }
+
+    /// Get the contents of a segment in the file.
+    pub fn segment_data(&self, segment: &ProgramHeader) -> Result<&'a [u8], Elf32Error> {
+        if !self.contains(segment) {
+            return Err(Elf32Error::SliceTooSmall(segment.file_end() as usize));
+        }
+        Ok(&self.0[segment.offset as usize..segment.file_end() as usize])
+    }
+}
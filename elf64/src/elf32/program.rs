@@ -0,0 +1,70 @@
+//! ELF-32 program headers
+//!
+//! The ELF-32 program header has the same meaning as the ELF-64 one, with
+//! 32-bit fields in a different order.
+
+use super::{Elf32, PHType, SegmentPermissions};
+
+/// An ELF-32 program header table entry.
+#[repr(C)]
+pub struct ProgramHeader {
+    type_: u32,
+    pub offset: u32,
+    pub vaddr: u32,
+    pub paddr: u32,
+    pub filesz: u32,
+    pub memsz: u32,
+    flags: u32,
+    pub align: u32,
+}
+
+impl ProgramHeader {
+    /// Get the type of a program header.
+    pub fn type_(&self) -> PHType {
+        self.type_.into()
+    }
+
+    /// Get the permissions of a segment.
+    pub fn permissions(&self) -> SegmentPermissions {
+        (self.flags & 7).into()
+    }
+
+    /// Get the end of the segment's contents in the file.
+    pub fn file_end(&self) -> u64 {
+        self.offset as u64 + self.filesz as u64
+    }
+}
+
+/// An iterator over the program headers in the program header table.
+pub struct ProgramHeaderIter<'a> {
+    _elf: &'a Elf32<'a>,
+    first: *const ProgramHeader,
+    num: u16,
+    current: u16,
+}
+
+impl<'a> ProgramHeaderIter<'a> {
+    /// Create an iterator over the entries of the program header table.
+    pub fn from_parts(e: &'a Elf32<'a>, f: *const ProgramHeader, n: u16) -> ProgramHeaderIter<'a> {
+        ProgramHeaderIter {
+            _elf: e,
+            first: f,
+            num: n,
+            current: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for ProgramHeaderIter<'a> {
+    type Item = &'a ProgramHeader;
+
+    fn next(&mut self) -> Option<&'a ProgramHeader> {
+        if self.current == self.num {
+            None
+        } else {
+            let ptr = self.first as usize + self.current as usize * core::mem::size_of::<ProgramHeader>();
+            self.current += 1;
+            unsafe { Some(&*(ptr as *const ProgramHeader)) }
+        }
+    }
+}
@@ -0,0 +1,583 @@
+//! Definitions and convenience functions for 64-bit ELF files
+//!
+//! This is an implementation based on the latest System V spec:
+//! <http://www.sco.com/developers/gabi/latest/contents.html>.
+//!
+//! The [`elf32`] module reads the header and program headers of 32-bit ELF
+//! files as well, for legacy kernels.
+//!
+//! The crate is `no_std` so that the loader can use it, and builds on the host
+//! as well so that the parser can be tested and fuzzed.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod dynamic;
+pub mod elf32;
+pub mod note;
+pub mod program;
+pub mod section;
+pub mod symbol;
+
+use core::mem::size_of;
+use core::result::Result;
+
+// Re-export modules to create a flat namespace.
+pub use dynamic::*;
+pub use note::*;
+pub use program::*;
+pub use section::*;
+pub use symbol::*;
+
+#[cfg(test)]
+mod tests;
+
+/// A set of errors that may arise.
+#[derive(Debug)]
+pub enum Elf64Error {
+    /// The provided slice is too small (usize holds required size).
+    SliceTooSmall(usize),
+    /// The slice is not an ELF file.
+    NotElf,
+    /// The slice is not an ELF-64 file.
+    NotElf64,
+    /// The version of the ELF file is invalid.
+    InvalidVersion,
+    /// The dynamic array or a table it points to is malformed.
+    BadDynamic,
+    /// The section header table or a section it points to is malformed.
+    BadSection,
+    /// The slice is not aligned for the ELF-64 structures it holds.
+    Misaligned,
+    /// The program header entry size is not the size of a `ProgramHeader`
+    /// (u16 holds the entry size).
+    BadProgramHeaderSize(u16),
+    /// The program header table is misaligned or extends past the end of the
+    /// address space (u64 holds its offset).
+    BadProgramHeaderTable(u64),
+    /// The contents of a segment extend past the end of the file (u64 holds
+    /// the offset of its program header).
+    SegmentOutOfBounds(u64),
+    /// A segment is smaller in memory than in the file, or extends past the end
+    /// of the address space (u64 holds the offset of its program header).
+    BadSegmentSize(u64),
+}
+
+/// The possible ABIs specified by the ELF file. Different ABIs may require
+/// different interpretations of various fields of ELF structures.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ElfAbi {
+    /// A plain ELF, System V ABI with no extensions.
+    None,
+    Unknown(u8),
+    // TODO: add Linux, BSD, etc.
+}
+
+impl From<u8> for ElfAbi {
+    // Matches a u8 to an ABI.
+    fn from(x: u8) -> ElfAbi {
+        match x {
+            0 => ElfAbi::None,
+            _ => ElfAbi::Unknown(x),
+        }
+    }
+}
+
+/// The possible data encoding methods for an ELF file.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ElfData {
+    /// 2LSB.
+    LittleEndian,
+    /// 2MSB.
+    BigEndian,
+    Unknown(u8),
+}
+
+impl From<u8> for ElfData {
+    // Matches a u8 to a data encoding method.
+    fn from(x: u8) -> ElfData {
+        match x {
+            1 => ElfData::LittleEndian,
+            2 => ElfData::BigEndian,
+            _ => ElfData::Unknown(x),
+        }
+    }
+}
+
+/// The possible types for an ELF file.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ElfType {
+    None,
+    Relocatable,
+    Executable,
+    SharedObject,
+    Core,
+    /// Specified by the operating system / environment.
+    EnvSpecified(u16),
+    /// Specified by the processor type.
+    ProcSpecified(u16),
+    Unknown,
+}
+
+impl From<u16> for ElfType {
+    // Matches a u16 to an ELF file type.
+    fn from(x: u16) -> ElfType {
+        match x {
+            0 => ElfType::None,
+            1 => ElfType::Relocatable,
+            2 => ElfType::Executable,
+            3 => ElfType::SharedObject,
+            4 => ElfType::Core,
+            0xfe00..=0xfeff => ElfType::EnvSpecified(x),
+            0xff00..=0xffff => ElfType::ProcSpecified(x),
+            _ => ElfType::Unknown,
+        }
+    }
+}
+
+/// The possible machine types for an ELF-64 file.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ElfMachine {
+    X86_64,
+    AArch64,
+    RiscV,
+    Unknown(u16),
+    // TODO: add other common machine types?
+}
+
+impl From<u16> for ElfMachine {
+    // Matches a u16 to a machine type.
+    fn from(x: u16) -> ElfMachine {
+        match x {
+            0x3E => ElfMachine::X86_64,
+            0xB7 => ElfMachine::AArch64,
+            0xF3 => ElfMachine::RiscV,
+            _ => ElfMachine::Unknown(x),
+        }
+    }
+}
+
+// The ELF-64 header.
+#[repr(C)]
+struct Elf64Header {
+    ident: [u8; 16],
+    type_: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+/// An ELF-64 object file in memory.
+pub struct Elf64<'a>(&'a [u8]);
+
+impl<'a> Elf64<'a> {
+    /// Checks a slice to see if it contains a valid ELF-64 header and program
+    /// header table, with segments that fit in the file, and returns an Elf64
+    /// structure.
+    pub fn from_slice(slice: &'a [u8]) -> Result<Elf64<'a>, Elf64Error> {
+        // The slice must be long enough to contain an ELF-64 header.
+        let header_size = size_of::<Elf64Header>();
+        if slice.len() < header_size {
+            return Err(Elf64Error::SliceTooSmall(header_size));
+        }
+
+        // The structures of the file are read in place.
+        if !(slice.as_ptr() as usize).is_multiple_of(core::mem::align_of::<Elf64Header>()) {
+            return Err(Elf64Error::Misaligned);
+        }
+
+        // The slice must begin with the ELF magic number.
+        if !slice.starts_with(&[0x7f, 0x45, 0x4C, 0x46]) {
+            return Err(Elf64Error::NotElf);
+        }
+
+        let header: &Elf64Header = unsafe {
+            // Safe because we checked that the slice starts with the ELF magic
+            // number and has a length of at least the size of an ELF-64 header.
+            &*(slice.as_ptr() as *const _ as *const Elf64Header)
+        };
+
+        // ident[4] (class) must equal 2 (64 bit).
+        if header.ident[4] != 2 {
+            return Err(Elf64Error::NotElf64);
+        }
+
+        // ident[6] (version) must equal current ELF version (1).
+        if header.ident[6] != 1 {
+            return Err(Elf64Error::InvalidVersion);
+        }
+
+        // Version must equal current ELF version (1).
+        if header.version != 1 {
+            return Err(Elf64Error::InvalidVersion);
+        }
+
+        let elf = Elf64(slice);
+        elf.check_segments()?;
+        Ok(elf)
+    }
+
+    // Check that the contents of each segment are in the file and that its
+    // size in memory is large enough to hold them.
+    fn check_segments(&self) -> Result<(), Elf64Error> {
+        let entry_offset = |x: usize| self.header().phoff + (x * size_of::<ProgramHeader>()) as u64;
+        for (x, segment) in self.program_headers()?.enumerate() {
+            if !self.contains(segment) {
+                return Err(Elf64Error::SegmentOutOfBounds(entry_offset(x)));
+            }
+            if segment.memsz < segment.filesz || segment.vaddr.checked_add(segment.memsz).is_none() {
+                return Err(Elf64Error::BadSegmentSize(entry_offset(x)));
+            }
+        }
+        Ok(())
+    }
+
+    // Get the header from an Elf64 struct.
+    fn header(&self) -> &'a Elf64Header {
+        unsafe {
+            // Safe because &self refers to a valid Elf64 struct, which can
+            // only exist if the slice contains a valid header.
+            &*(self.0.as_ptr() as *const _ as *const Elf64Header)
+        }
+    }
+
+    /// Get the data encoding of the ELF.
+    pub fn data(&self) -> ElfData {
+        self.header().ident[5].into()
+    }
+
+    /// Get the ABI of the ELF.
+    pub fn abi(&self) -> ElfAbi {
+        self.header().ident[7].into()
+    }
+
+    /// Get the ABI version of the ELF.
+    pub fn abi_version(&self) -> u8 {
+        self.header().ident[8]
+    }
+
+    /// Get the file type of the ELF.
+    pub fn file_type(&self) -> ElfType {
+        self.header().type_.into()
+    }
+
+    /// Get the machine type of the ELF.
+    pub fn machine(&self) -> ElfMachine {
+        self.header().machine.into()
+    }
+
+    /// Get the entry point of the ELF.
+    pub fn entry(&self) -> u64 {
+        self.header().entry
+    }
+
+    /// Check if the ELF can run on the current machine.
+    pub fn is_valid_locally(&self) -> bool {
+        // For x86_64 targets, encoding must be little endian and machine must match.
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.data() == ElfData::LittleEndian && self.machine() == ElfMachine::X86_64
+        }
+
+        // The loader only runs on x86_64, so nothing is valid on other hosts.
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            false
+        }
+    }
+
+    /// Get an iterator over the entries of the program header table.
+    pub fn program_headers(&self) -> Result<ProgramHeaderIter<'_>, Elf64Error> {
+        let header = self.header();
+        if header.phnum == 0 {
+            return Ok(ProgramHeaderIter::from_parts(self, core::ptr::null::<ProgramHeader>(), 0));
+        }
+        if header.phentsize as usize != size_of::<ProgramHeader>() {
+            return Err(Elf64Error::BadProgramHeaderSize(header.phentsize));
+        }
+        if !(header.phoff as usize).is_multiple_of(core::mem::align_of::<ProgramHeader>()) {
+            return Err(Elf64Error::BadProgramHeaderTable(header.phoff));
+        }
+
+        // Check if the slice is long enough to contain the program header table.
+        let required_size = table_end(header.phoff, header.phnum as usize, size_of::<ProgramHeader>())
+            .ok_or(Elf64Error::BadProgramHeaderTable(header.phoff))?;
+        if self.0.len() < required_size {
+            return Err(Elf64Error::SliceTooSmall(required_size))
+        }
+
+        // In memory, the program header table starts at the address of the ELF
+        // buffer plus the offset.
+        let start = self.0.as_ptr() as usize + self.header().phoff as usize;
+        Ok(ProgramHeaderIter::from_parts(self, start as *const ProgramHeader, self.header().phnum))
+    }
+
+    /// Check if the contents of a segment are contained in the file.
+    pub fn contains(&self, segment: &'a ProgramHeader) -> bool {
+        match segment.file_end() {
+            Some(end) => end <= self.0.len() as u64,
+            None => false,
+        }
+    }
+
+    /// Get the contents of a segment in the file.
+    pub fn segment_data(&self, segment: &'a ProgramHeader) -> Result<&'a [u8], Elf64Error> {
+        if !self.contains(segment) {
+            let end = segment.file_end().unwrap_or(u64::MAX);
+            return Err(Elf64Error::SliceTooSmall(end as usize));
+        }
+        Ok(&self.0[segment.offset as usize..(segment.offset + segment.filesz) as usize])
+    }
+
+    /// Get an iterator over the notes of a note segment.
+    pub fn segment_notes(&self, segment: &'a ProgramHeader) -> Result<NoteIter<'a>, Elf64Error> {
+        let data = self.segment_data(segment)?;
+        Ok(NoteIter::from_parts(data, segment.align as usize))
+    }
+
+    /// Get the x86 features (`GNU_PROPERTY_X86_FEATURE_1_*` bits) that the
+    /// program is compatible with, from its GNU program property note. A
+    /// program without the note is compatible with none.
+    pub fn x86_features(&self) -> Result<u32, Elf64Error> {
+        let segment = match self.program_headers()?.find(|s| s.type_() == PHType::GnuProperty) {
+            Some(segment) => segment,
+            None => return Ok(0),
+        };
+        for note in self.segment_notes(segment)? {
+            if note.name != b"GNU" || note.type_ != NT_GNU_PROPERTY_TYPE_0 {
+                continue;
+            }
+            for property in PropertyIter::from_parts(note.desc) {
+                if property.type_ == GNU_PROPERTY_X86_FEATURE_1_AND && property.data.len() == 4 {
+                    let data = property.data;
+                    return Ok(u32::from_le_bytes([data[0], data[1], data[2], data[3]]));
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    /// Get an iterator over the dynamic array, if the ELF has one.
+    pub fn dynamic(&self) -> Result<Option<DynIter<'_>>, Elf64Error> {
+        let segment = match self.program_headers()?.find(|s| s.type_() == PHType::Dynamic) {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+        let data = self.segment_data(segment)?;
+        if !(segment.offset as usize).is_multiple_of(core::mem::align_of::<Dyn>()) {
+            return Err(Elf64Error::BadDynamic);
+        }
+
+        let n = data.len() / size_of::<Dyn>();
+        Ok(Some(DynIter::from_parts(self, data.as_ptr() as *const Dyn, n)))
+    }
+
+    /// Get the value of the first dynamic array entry with the given tag.
+    pub fn dynamic_value(&self, tag: DynTag) -> Result<Option<u64>, Elf64Error> {
+        match self.dynamic()? {
+            Some(mut entries) => Ok(entries.find(|e| e.tag() == tag).map(|e| e.val)),
+            None => Ok(None),
+        }
+    }
+
+    /// Translate a virtual address to an offset in the file, using the
+    /// loadable segments.
+    pub fn vaddr_to_offset(&self, vaddr: u64) -> Option<u64> {
+        self.program_headers()
+            .ok()?
+            .find(|s| s.type_() == PHType::Load && vaddr >= s.vaddr && vaddr - s.vaddr < s.filesz)
+            .map(|s| s.offset + (vaddr - s.vaddr))
+    }
+
+    /// Get an iterator over the relocations with explicit addends named by
+    /// the dynamic array.
+    pub fn rela_entries(&self) -> Result<RelaIter<'_>, Elf64Error> {
+        let (addr, size) = match (
+            self.dynamic_value(DynTag::Rela)?,
+            self.dynamic_value(DynTag::RelaSz)?,
+        ) {
+            (Some(addr), Some(size)) => (addr, size as usize),
+            _ => return Ok(RelaIter::from_parts(self, core::ptr::null::<Rela>(), 0)),
+        };
+        let entsize = self.dynamic_value(DynTag::RelaEnt)?.unwrap_or(size_of::<Rela>() as u64);
+        if entsize as usize != size_of::<Rela>() {
+            return Err(Elf64Error::BadDynamic);
+        }
+
+        let offset = self.vaddr_to_offset(addr).ok_or(Elf64Error::BadDynamic)? as usize;
+        if !offset.is_multiple_of(core::mem::align_of::<Rela>()) {
+            return Err(Elf64Error::BadDynamic);
+        }
+        let end = offset.checked_add(size).ok_or(Elf64Error::BadDynamic)?;
+        if self.0.len() < end {
+            return Err(Elf64Error::SliceTooSmall(end));
+        }
+
+        let start = self.0.as_ptr() as usize + offset;
+        Ok(RelaIter::from_parts(self, start as *const Rela, size / size_of::<Rela>()))
+    }
+
+    /// Get an entry of the dynamic symbol table.
+    pub fn dynamic_symbol(&self, index: u32) -> Result<&'a Symbol, Elf64Error> {
+        let addr = self.dynamic_value(DynTag::SymTab)?.ok_or(Elf64Error::BadDynamic)?;
+        let entsize = self.dynamic_value(DynTag::SymEnt)?.unwrap_or(size_of::<Symbol>() as u64);
+        if entsize as usize != size_of::<Symbol>() {
+            return Err(Elf64Error::BadDynamic);
+        }
+
+        let offset = self.vaddr_to_offset(addr).ok_or(Elf64Error::BadDynamic)? as usize;
+        if !offset.is_multiple_of(core::mem::align_of::<Symbol>()) {
+            return Err(Elf64Error::BadDynamic);
+        }
+        let end = table_end(offset as u64, index as usize + 1, size_of::<Symbol>())
+            .ok_or(Elf64Error::BadDynamic)?;
+        if self.0.len() < end {
+            return Err(Elf64Error::SliceTooSmall(end));
+        }
+
+        let ptr = self.0.as_ptr() as usize + offset + index as usize * size_of::<Symbol>();
+        Ok(unsafe { &*(ptr as *const Symbol) })
+    }
+
+    /// Get an iterator over the entries of the section header table.
+    pub fn section_headers(&self) -> Result<SectionHeaderIter<'_>, Elf64Error> {
+        let header = self.header();
+        if header.shnum == 0 {
+            return Ok(SectionHeaderIter::from_parts(self, core::ptr::null::<SectionHeader>(), 0));
+        }
+        if header.shentsize as usize != size_of::<SectionHeader>()
+            || !(header.shoff as usize).is_multiple_of(core::mem::align_of::<SectionHeader>())
+        {
+            return Err(Elf64Error::BadSection);
+        }
+
+        // Check if the slice is long enough to contain the section header table.
+        let required_size = table_end(header.shoff, header.shnum as usize, size_of::<SectionHeader>())
+            .ok_or(Elf64Error::BadSection)?;
+        if self.0.len() < required_size {
+            return Err(Elf64Error::SliceTooSmall(required_size));
+        }
+
+        let start = self.0.as_ptr() as usize + header.shoff as usize;
+        Ok(SectionHeaderIter::from_parts(self, start as *const SectionHeader, header.shnum))
+    }
+
+    /// Get the number of entries in the section header table.
+    pub fn section_count(&self) -> u16 {
+        self.header().shnum
+    }
+
+    /// Get the index of the section holding the section names.
+    pub fn section_names_index(&self) -> u16 {
+        self.header().shstrndx
+    }
+
+    /// Get a section header by its index in the section header table.
+    pub fn section_header(&self, index: u32) -> Result<&SectionHeader, Elf64Error> {
+        self.section_headers()?
+            .nth(index as usize)
+            .ok_or(Elf64Error::BadSection)
+    }
+
+    /// Get the contents of a section in the file. Sections that occupy no
+    /// space in the file have empty contents.
+    pub fn section_data(&self, section: &SectionHeader) -> Result<&'a [u8], Elf64Error> {
+        if section.type_() == SHType::NoBits {
+            return Ok(&[]);
+        }
+        let end = (section.offset as usize)
+            .checked_add(section.size as usize)
+            .ok_or(Elf64Error::BadSection)?;
+        if self.0.len() < end {
+            return Err(Elf64Error::SliceTooSmall(end));
+        }
+        Ok(&self.0[section.offset as usize..end])
+    }
+
+    /// Get an iterator over the notes of a note section.
+    pub fn section_notes(&self, section: &SectionHeader) -> Result<NoteIter<'a>, Elf64Error> {
+        let data = self.section_data(section)?;
+        Ok(NoteIter::from_parts(data, section.addralign as usize))
+    }
+
+    /// Get a NUL-terminated string from a string table section.
+    pub fn string(&self, table: &SectionHeader, offset: u32) -> Result<&'a str, Elf64Error> {
+        if table.type_() != SHType::StrTab {
+            return Err(Elf64Error::BadSection);
+        }
+        let data = self.section_data(table)?;
+        let bytes = data.get(offset as usize..).ok_or(Elf64Error::BadSection)?;
+        let length = bytes.iter().position(|&b| b == 0).ok_or(Elf64Error::BadSection)?;
+        core::str::from_utf8(&bytes[..length]).map_err(|_| Elf64Error::BadSection)
+    }
+
+    /// Get the name of a section from the section name string table.
+    pub fn section_name(&self, section: &SectionHeader) -> Result<&'a str, Elf64Error> {
+        let names = self.section_header(self.section_names_index() as u32)?;
+        self.string(names, section.name_offset())
+    }
+
+    /// Find a section by its name.
+    pub fn section_by_name(&self, name: &str) -> Result<Option<&SectionHeader>, Elf64Error> {
+        for section in self.section_headers()? {
+            if self.section_name(section)? == name {
+                return Ok(Some(section));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the symbol table section, if the ELF has not been stripped of it.
+    pub fn symbol_table(&self) -> Result<Option<&SectionHeader>, Elf64Error> {
+        Ok(self.section_headers()?.find(|s| s.type_() == SHType::SymTab))
+    }
+
+    /// Get an iterator over the entries of the symbol table, which is empty if
+    /// the ELF has no symbol table.
+    pub fn symbols(&self) -> Result<SymbolIter<'_>, Elf64Error> {
+        let table = match self.symbol_table()? {
+            Some(table) => table,
+            None => return Ok(SymbolIter::from_parts(self, core::ptr::null::<Symbol>(), 0)),
+        };
+        if table.entsize as usize != size_of::<Symbol>()
+            || !(table.offset as usize).is_multiple_of(core::mem::align_of::<Symbol>())
+        {
+            return Err(Elf64Error::BadSection);
+        }
+
+        let data = self.section_data(table)?;
+        Ok(SymbolIter::from_parts(self, data.as_ptr() as *const Symbol, data.len() / size_of::<Symbol>()))
+    }
+
+    /// Get the name of an entry of the symbol table.
+    pub fn symbol_name(&self, symbol: &Symbol) -> Result<&'a str, Elf64Error> {
+        let table = self.symbol_table()?.ok_or(Elf64Error::BadSection)?;
+        let strings = self.section_header(table.link)?;
+        self.string(strings, symbol.name_offset())
+    }
+
+    /// Find an entry of the symbol table by its name.
+    pub fn find_symbol(&self, name: &str) -> Result<Option<&Symbol>, Elf64Error> {
+        for symbol in self.symbols()? {
+            if symbol.name_offset() != 0 && self.symbol_name(symbol)? == name {
+                return Ok(Some(symbol));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// Get the end of a table of `count` entries of `size` bytes at an offset, or
+// None if it does not fit in the address space.
+fn table_end(offset: u64, count: usize, size: usize) -> Option<usize> {
+    let length = count.checked_mul(size)?;
+    (offset as usize).checked_add(length)
+}
@@ -0,0 +1,121 @@
+//! ELF notes
+//!
+//! Note segments and sections hold a sequence of notes, each made of an owner
+//! name, a type defined by the owner and a descriptor holding its contents.
+//!
+//! The GNU program property note lists properties of the program, such as the
+//! x86 control-flow protection features it is compatible with.
+
+/// The type of the GNU note holding program properties.
+pub const NT_GNU_PROPERTY_TYPE_0: u32 = 5;
+
+/// The property holding the x86 features that all parts of a program are
+/// compatible with.
+pub const GNU_PROPERTY_X86_FEATURE_1_AND: u32 = 0xc0000002;
+
+/// The x86 feature bit for indirect branch tracking (CET IBT).
+pub const GNU_PROPERTY_X86_FEATURE_1_IBT: u32 = 1 << 0;
+
+/// The x86 feature bit for shadow stacks (CET SHSTK).
+pub const GNU_PROPERTY_X86_FEATURE_1_SHSTK: u32 = 1 << 1;
+
+/// An ELF note.
+pub struct Note<'a> {
+    /// The name of the note's owner, without its NUL terminator.
+    pub name: &'a [u8],
+    /// The type of the note, interpreted according to its owner.
+    pub type_: u32,
+    /// The contents of the note.
+    pub desc: &'a [u8],
+}
+
+/// An iterator over the notes in a note segment or section.
+///
+/// Iteration stops at the first note that does not fit in the data.
+pub struct NoteIter<'a> {
+    data: &'a [u8],
+    align: usize,
+}
+
+impl<'a> NoteIter<'a> {
+    /// Create an iterator over the notes in a slice, whose fields are padded to
+    /// the given alignment (4 for most notes, 8 for some 64-bit ones).
+    pub fn from_parts(data: &'a [u8], align: usize) -> NoteIter<'a> {
+        NoteIter {
+            data,
+            align: if align == 8 { 8 } else { 4 },
+        }
+    }
+
+    // Round a size up to the alignment of the note's fields.
+    fn pad(&self, size: usize) -> Option<usize> {
+        Some(size.checked_add(self.align - 1)? & !(self.align - 1))
+    }
+}
+
+impl<'a> Iterator for NoteIter<'a> {
+    type Item = Note<'a>;
+
+    fn next(&mut self) -> Option<Note<'a>> {
+        // The header holds the name size, descriptor size and type.
+        let header = self.data.get(..12)?;
+        let word = |x: usize| u32::from_le_bytes([header[x], header[x + 1], header[x + 2], header[x + 3]]);
+        let namesz = word(0) as usize;
+        let descsz = word(4) as usize;
+        let type_ = word(8);
+
+        // The name follows the header, the descriptor is aligned after it.
+        let desc_start = self.pad(12usize.checked_add(namesz)?)?;
+        let end = self.pad(desc_start.checked_add(descsz)?)?;
+        let name = self.data.get(12..12 + namesz)?;
+        let desc = self.data.get(desc_start..desc_start + descsz)?;
+
+        // The last note may lack padding after its descriptor.
+        self.data = self.data.get(end..).unwrap_or(&[]);
+        Some(Note {
+            name: name.strip_suffix(&[0]).unwrap_or(name),
+            type_,
+            desc,
+        })
+    }
+}
+
+/// A property in a GNU program property note.
+pub struct Property<'a> {
+    /// The type of the property.
+    pub type_: u32,
+    /// The contents of the property.
+    pub data: &'a [u8],
+}
+
+/// An iterator over the properties in the descriptor of a GNU program
+/// property note, which are padded to 8 bytes in ELF-64 files.
+///
+/// Iteration stops at the first property that does not fit in the descriptor.
+pub struct PropertyIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PropertyIter<'a> {
+    /// Create an iterator over the properties in a note descriptor.
+    pub fn from_parts(data: &'a [u8]) -> PropertyIter<'a> {
+        PropertyIter { data }
+    }
+}
+
+impl<'a> Iterator for PropertyIter<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        // The header holds the type and size of the property.
+        let header = self.data.get(..8)?;
+        let word = |x: usize| u32::from_le_bytes([header[x], header[x + 1], header[x + 2], header[x + 3]]);
+        let type_ = word(0);
+        let size = word(4) as usize;
+
+        let data = self.data.get(8..8usize.checked_add(size)?)?;
+        let end = (8 + size).checked_add(7)? & !7;
+        self.data = self.data.get(end..).unwrap_or(&[]);
+        Some(Property { type_, data })
+    }
+}
@@ -24,6 +24,12 @@ pub enum PHType {
     ProgramHeader,
     /// An entry specifying thread-local storage, "need not supported."
     ThreadLocalStorage,
+    /// A GNU entry whose flags give the permissions of the stack.
+    GnuStack,
+    /// A GNU entry describing a range to make read-only after relocation.
+    GnuRelro,
+    /// A GNU entry with the program property note.
+    GnuProperty,
     /// Specified by the operating system / environment.
     EnvSpecified(u32),
     /// Specified by the processor type.
@@ -43,6 +49,9 @@ impl From<u32> for PHType {
             5 => PHType::ShLib,
             6 => PHType::ProgramHeader,
             7 => PHType::ThreadLocalStorage,
+            0x6474e551 => PHType::GnuStack,
+            0x6474e552 => PHType::GnuRelro,
+            0x6474e553 => PHType::GnuProperty,
             0x60000000..=0x6fffffff => PHType::EnvSpecified(x),
             0x70000000..=0x7fffffff => PHType::ProcSpecified(x),
             _ => PHType::Unknown,
@@ -51,6 +60,7 @@ impl From<u32> for PHType {
 }
 
 /// Possible permissions for an ELF segment.
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum SegmentPermissions {
     None,
     R,
@@ -80,6 +90,30 @@ impl From<u32> for SegmentPermissions {
     }
 }
 
+impl SegmentPermissions {
+    /// Check if the segment may be written to.
+    pub fn is_writable(&self) -> bool {
+        matches!(
+            self,
+            SegmentPermissions::W
+                | SegmentPermissions::RW
+                | SegmentPermissions::WX
+                | SegmentPermissions::RWX
+        )
+    }
+
+    /// Check if the segment may be executed.
+    pub fn is_executable(&self) -> bool {
+        matches!(
+            self,
+            SegmentPermissions::X
+                | SegmentPermissions::RX
+                | SegmentPermissions::WX
+                | SegmentPermissions::RWX
+        )
+    }
+}
+
 /// An ELF-64 program header table entry.
 #[repr(C)]
 pub struct ProgramHeader {
@@ -98,6 +132,17 @@ impl ProgramHeader {
     pub fn type_(&self) -> PHType {
         self.type_.into()
     }
+
+    /// Get the permissions of the segment, ignoring OS and processor specific flags.
+    pub fn permissions(&self) -> SegmentPermissions {
+        (self.flags & 7).into()
+    }
+
+    /// Get the end of the segment's contents in the file, or None if it
+    /// overflows.
+    pub fn file_end(&self) -> Option<u64> {
+        self.offset.checked_add(self.filesz)
+    }
 }
 
 /// An iterator over the program headers in the program header table.
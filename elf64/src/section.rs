@@ -0,0 +1,147 @@
+//! ELF section headers
+//!
+//! The section header table describes the sections of an object file, such as
+//! its symbol and string tables. Sections are not needed to load a program, but
+//! they hold information useful for debugging it.
+
+use super::Elf64;
+
+/// Possible types for a section header table entry.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SHType {
+    Null,
+    /// A section with contents defined by the program.
+    ProgBits,
+    /// A symbol table for link editing.
+    SymTab,
+    /// A string table.
+    StrTab,
+    /// Relocations with explicit addends.
+    Rela,
+    /// A symbol hash table.
+    Hash,
+    /// Dynamic linking information.
+    Dynamic,
+    /// Auxiliary information about the file.
+    Note,
+    /// A section that occupies no space in the file, such as `.bss`.
+    NoBits,
+    /// Relocations without explicit addends.
+    Rel,
+    /// Reserved, with unspecified semantics.
+    ShLib,
+    /// A minimal symbol table for dynamic linking.
+    DynSym,
+    /// An array of pointers to initialization functions.
+    InitArray,
+    /// An array of pointers to termination functions.
+    FiniArray,
+    /// An array of pointers to functions called before other initialization functions.
+    PreinitArray,
+    /// A section group.
+    Group,
+    /// Extended section indices for a symbol table.
+    SymTabShndx,
+    /// Specified by the operating system / environment.
+    EnvSpecified(u32),
+    /// Specified by the processor type.
+    ProcSpecified(u32),
+    Unknown(u32),
+}
+
+impl From<u32> for SHType {
+    // Matches a u32 to a section header table entry type.
+    fn from(x: u32) -> SHType {
+        match x {
+            0 => SHType::Null,
+            1 => SHType::ProgBits,
+            2 => SHType::SymTab,
+            3 => SHType::StrTab,
+            4 => SHType::Rela,
+            5 => SHType::Hash,
+            6 => SHType::Dynamic,
+            7 => SHType::Note,
+            8 => SHType::NoBits,
+            9 => SHType::Rel,
+            10 => SHType::ShLib,
+            11 => SHType::DynSym,
+            14 => SHType::InitArray,
+            15 => SHType::FiniArray,
+            16 => SHType::PreinitArray,
+            17 => SHType::Group,
+            18 => SHType::SymTabShndx,
+            0x60000000..=0x6fffffff => SHType::EnvSpecified(x),
+            0x70000000..=0x7fffffff => SHType::ProcSpecified(x),
+            _ => SHType::Unknown(x),
+        }
+    }
+}
+
+/// An ELF-64 section header table entry.
+#[repr(C)]
+pub struct SectionHeader {
+    name: u32,
+    type_: u32,
+    /// Attribute flags of the section.
+    pub flags: u64,
+    /// The virtual address of the section in memory, or zero if it is not loaded.
+    pub addr: u64,
+    /// The offset of the section's contents in the file.
+    pub offset: u64,
+    /// The size of the section in bytes.
+    pub size: u64,
+    /// The index of an associated section, such as the string table of a symbol table.
+    pub link: u32,
+    /// Extra information, depending on the section type.
+    pub info: u32,
+    /// The required alignment of the section.
+    pub addralign: u64,
+    /// The size of each entry, for sections holding a table of fixed-size entries.
+    pub entsize: u64,
+}
+
+impl SectionHeader {
+    /// Get the type of a section header.
+    pub fn type_(&self) -> SHType {
+        self.type_.into()
+    }
+
+    /// Get the offset of the section's name in the section name string table.
+    pub fn name_offset(&self) -> u32 {
+        self.name
+    }
+}
+
+/// An iterator over the section headers in the section header table.
+pub struct SectionHeaderIter<'a> {
+    _elf: &'a Elf64<'a>,
+    first: *const SectionHeader,
+    num: u16,
+    current: u16,
+}
+
+impl<'a> SectionHeaderIter<'a> {
+    /// Create an iterator over the entries of the section header table.
+    pub fn from_parts(e: &'a Elf64, f: *const SectionHeader, n: u16) -> SectionHeaderIter<'a> {
+        SectionHeaderIter {
+            _elf: e,
+            first: f,
+            num: n,
+            current: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for SectionHeaderIter<'a> {
+    type Item = &'a SectionHeader;
+
+    fn next(&mut self) -> Option<&'a SectionHeader> {
+        if self.current == self.num {
+            None
+        } else {
+            let ptr = self.first as usize + self.current as usize * core::mem::size_of::<SectionHeader>();
+            self.current += 1;
+            unsafe { Some(&*(ptr as *const SectionHeader)) }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! ELF symbols
+//!
+//! Symbol table entries give the name, value and binding of the symbols that
+//! an object file defines or refers to.
+
+use super::Elf64;
+
+/// Possible bindings for a symbol.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SymbolBinding {
+    /// Not visible outside the object file.
+    Local,
+    /// Visible to all object files being combined.
+    Global,
+    /// Like global, but with lower precedence and allowed to be undefined.
+    Weak,
+    /// Specified by the operating system / environment.
+    EnvSpecified(u8),
+    /// Specified by the processor type.
+    ProcSpecified(u8),
+    Unknown(u8),
+}
+
+impl From<u8> for SymbolBinding {
+    // Matches a u8 to a symbol binding.
+    fn from(x: u8) -> SymbolBinding {
+        match x {
+            0 => SymbolBinding::Local,
+            1 => SymbolBinding::Global,
+            2 => SymbolBinding::Weak,
+            10..=12 => SymbolBinding::EnvSpecified(x),
+            13..=15 => SymbolBinding::ProcSpecified(x),
+            _ => SymbolBinding::Unknown(x),
+        }
+    }
+}
+
+/// Possible types for a symbol.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SymbolType {
+    /// The type is not specified.
+    NoType,
+    /// A data object, such as a variable.
+    Object,
+    /// A function or other executable code.
+    Func,
+    /// A section, used for relocations.
+    Section,
+    /// The source file the object file was built from.
+    File,
+    /// An uninitialized common block.
+    Common,
+    /// A thread-local storage entity.
+    Tls,
+    /// Specified by the operating system / environment.
+    EnvSpecified(u8),
+    /// Specified by the processor type.
+    ProcSpecified(u8),
+    Unknown(u8),
+}
+
+impl From<u8> for SymbolType {
+    // Matches a u8 to a symbol type.
+    fn from(x: u8) -> SymbolType {
+        match x {
+            0 => SymbolType::NoType,
+            1 => SymbolType::Object,
+            2 => SymbolType::Func,
+            3 => SymbolType::Section,
+            4 => SymbolType::File,
+            5 => SymbolType::Common,
+            6 => SymbolType::Tls,
+            10..=12 => SymbolType::EnvSpecified(x),
+            13..=15 => SymbolType::ProcSpecified(x),
+            _ => SymbolType::Unknown(x),
+        }
+    }
+}
+
+/// The section index of undefined symbols.
+pub const SHN_UNDEF: u16 = 0;
+
+/// The section index of symbols with absolute values that are not affected by relocation.
+pub const SHN_ABS: u16 = 0xfff1;
+
+/// An ELF-64 symbol table entry.
+#[repr(C)]
+pub struct Symbol {
+    name: u32,
+    info: u8,
+    other: u8,
+    /// The index of the section the symbol is defined in.
+    pub shndx: u16,
+    /// The value of the symbol, usually its virtual address.
+    pub value: u64,
+    /// The size of the object the symbol refers to.
+    pub size: u64,
+}
+
+impl Symbol {
+    /// Get the binding of a symbol.
+    pub fn binding(&self) -> SymbolBinding {
+        (self.info >> 4).into()
+    }
+
+    /// Get the type of a symbol.
+    pub fn type_(&self) -> SymbolType {
+        (self.info & 0xf).into()
+    }
+
+    /// Get the offset of the symbol's name in the associated string table.
+    pub fn name_offset(&self) -> u32 {
+        self.name
+    }
+
+    /// Check if a symbol is undefined in the object file.
+    pub fn is_undefined(&self) -> bool {
+        self.shndx == SHN_UNDEF
+    }
+}
+
+/// An iterator over the entries of a symbol table.
+pub struct SymbolIter<'a> {
+    _elf: &'a Elf64<'a>,
+    first: *const Symbol,
+    num: usize,
+    current: usize,
+}
+
+impl<'a> SymbolIter<'a> {
+    /// Create an iterator over the entries of a symbol table.
+    pub fn from_parts(e: &'a Elf64, f: *const Symbol, n: usize) -> SymbolIter<'a> {
+        SymbolIter {
+            _elf: e,
+            first: f,
+            num: n,
+            current: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for SymbolIter<'a> {
+    type Item = &'a Symbol;
+
+    fn next(&mut self) -> Option<&'a Symbol> {
+        if self.current == self.num {
+            None
+        } else {
+            let ptr = self.first as usize + self.current * core::mem::size_of::<Symbol>();
+            self.current += 1;
+            unsafe { Some(&*(ptr as *const Symbol)) }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+// Unit tests, run on the host against the fixtures generated by build.rs
+
+use super::*;
+
+// An executable linked in the higher half, with section headers and symbols.
+const EXECUTABLE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/executable.elf"));
+
+// A position-independent executable with relocations and no section headers.
+const POSITION_INDEPENDENT: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/position_independent.elf"));
+
+// A 32-bit executable loaded at 1 MiB, with no section headers.
+const EXECUTABLE32: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/executable32.elf"));
+
+// The link address of the executable.
+const BASE: u64 = 0xffffffff80000000;
+
+// The load address of the 32-bit executable.
+const BASE32: u32 = 0x100000;
+
+// Offsets of ELF header fields.
+const E_PHOFF: usize = 32;
+const E_PHENTSIZE: usize = 54;
+const E_PHNUM: usize = 56;
+const E_SHENTSIZE: usize = 58;
+
+// Offsets of program header fields, from the first program header.
+const P_OFFSET: usize = 64 + 8;
+const P_FILESZ: usize = 64 + 32;
+const P_MEMSZ: usize = 64 + 40;
+
+// Offsets of ELF-32 header and program header fields.
+const E32_PHENTSIZE: usize = 42;
+const P32_PADDR: usize = 52 + 12;
+const P32_FILESZ: usize = 52 + 16;
+const P32_MEMSZ: usize = 52 + 20;
+
+// A copy of a fixture aligned like the loader's page-aligned file buffers, so
+// that it can be modified to make it malformed.
+struct Fixture {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Fixture {
+    fn new(bytes: &[u8]) -> Fixture {
+        let mut fixture = Fixture {
+            words: vec![0; bytes.len().div_ceil(8)],
+            len: bytes.len(),
+        };
+        fixture.bytes_mut().copy_from_slice(bytes);
+        fixture
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.len) }
+    }
+
+    fn set_u32(&mut self, offset: usize, x: u32) {
+        self.bytes_mut()[offset..offset + 4].copy_from_slice(&x.to_le_bytes());
+    }
+
+    fn set_u16(&mut self, offset: usize, x: u16) {
+        self.bytes_mut()[offset..offset + 2].copy_from_slice(&x.to_le_bytes());
+    }
+
+    fn set_u64(&mut self, offset: usize, x: u64) {
+        self.bytes_mut()[offset..offset + 8].copy_from_slice(&x.to_le_bytes());
+    }
+}
+
+#[test]
+fn parses_header() {
+    let fixture = Fixture::new(EXECUTABLE);
+    let elf = Elf64::from_slice(fixture.bytes()).unwrap();
+    assert_eq!(elf.data(), ElfData::LittleEndian);
+    assert_eq!(elf.abi(), ElfAbi::None);
+    assert_eq!(elf.file_type(), ElfType::Executable);
+    assert_eq!(elf.machine(), ElfMachine::X86_64);
+    assert_eq!(elf.entry(), BASE + 0x1000);
+    assert_eq!(elf.is_valid_locally(), cfg!(target_arch = "x86_64"));
+}
+
+#[test]
+fn iterates_program_headers() {
+    let fixture = Fixture::new(EXECUTABLE);
+    let elf = Elf64::from_slice(fixture.bytes()).unwrap();
+    let segments: Vec<_> = elf.program_headers().unwrap().collect();
+    assert_eq!(segments.len(), 3);
+
+    assert_eq!(segments[0].type_(), PHType::Load);
+    assert_eq!(segments[0].permissions(), SegmentPermissions::RX);
+    assert_eq!(segments[0].vaddr, BASE + 0x1000);
+    assert_eq!(
+        &elf.segment_data(segments[0]).unwrap()[..3],
+        &[0xf4, 0xeb, 0xfd]
+    );
+
+    assert_eq!(segments[1].type_(), PHType::Load);
+    assert_eq!(segments[1].permissions(), SegmentPermissions::RW);
+    assert_eq!((segments[1].filesz, segments[1].memsz), (0x28, 0x1000));
+
+    assert_eq!(segments[2].type_(), PHType::Note);
+    assert!(segments.iter().all(|s| elf.contains(s)));
+}
+
+#[test]
+fn reads_notes() {
+    let fixture = Fixture::new(EXECUTABLE);
+    let elf = Elf64::from_slice(fixture.bytes()).unwrap();
+    let segment = elf
+        .program_headers()
+        .unwrap()
+        .find(|s| s.type_() == PHType::Note)
+        .unwrap();
+    let notes: Vec<_> = elf.segment_notes(segment).unwrap().collect();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].name, b"uefi-boot");
+    assert_eq!(notes[0].type_, 2);
+    assert_eq!(notes[0].desc, &0x10000u64.to_le_bytes());
+
+    let section = elf.section_by_name(".note.uefi-boot").unwrap().unwrap();
+    assert_eq!(elf.section_notes(section).unwrap().count(), 1);
+}
+
+#[test]
+fn reads_sections_and_symbols() {
+    let fixture = Fixture::new(EXECUTABLE);
+    let elf = Elf64::from_slice(fixture.bytes()).unwrap();
+    assert_eq!(elf.section_count(), 7);
+    assert_eq!(elf.section_names_index(), 6);
+
+    let names: Vec<_> = elf
+        .section_headers()
+        .unwrap()
+        .map(|s| elf.section_name(s).unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "",
+            ".text",
+            ".data",
+            ".note.uefi-boot",
+            ".symtab",
+            ".strtab",
+            ".shstrtab"
+        ]
+    );
+    assert!(elf.section_by_name(".bss").unwrap().is_none());
+
+    assert_eq!(elf.symbols().unwrap().count(), 2);
+    let symbol = elf.find_symbol("_start").unwrap().unwrap();
+    assert_eq!(symbol.value, BASE + 0x1000);
+    assert_eq!(symbol.type_(), SymbolType::Func);
+    assert_eq!(symbol.binding(), SymbolBinding::Global);
+}
+
+#[test]
+fn reads_relocations() {
+    let fixture = Fixture::new(POSITION_INDEPENDENT);
+    let elf = Elf64::from_slice(fixture.bytes()).unwrap();
+    assert_eq!(elf.file_type(), ElfType::SharedObject);
+    assert_eq!(elf.section_count(), 0);
+    assert_eq!(elf.section_headers().unwrap().count(), 0);
+
+    assert_eq!(elf.dynamic_value(DynTag::Rela).unwrap(), Some(0x2080));
+    assert_eq!(elf.dynamic_value(DynTag::TextRel).unwrap(), None);
+    let relocations: Vec<_> = elf.rela_entries().unwrap().collect();
+    assert_eq!(relocations.len(), 2);
+    assert_eq!(
+        (
+            relocations[0].offset,
+            relocations[0].type_(),
+            relocations[0].addend
+        ),
+        (0x2100, 8, 0x1000)
+    );
+    assert_eq!((relocations[1].sym(), relocations[1].type_()), (1, 1));
+    assert_eq!(elf.dynamic_symbol(1).unwrap().value, 0x1000);
+
+    assert_eq!(elf.vaddr_to_offset(0x2108), Some(0x2108));
+    assert_eq!(elf.vaddr_to_offset(0x3000), None);
+}
+
+#[test]
+fn reads_gnu_segments() {
+    let fixture = Fixture::new(POSITION_INDEPENDENT);
+    let elf = Elf64::from_slice(fixture.bytes()).unwrap();
+    let segments: Vec<_> = elf.program_headers().unwrap().collect();
+    assert_eq!(segments[3].type_(), PHType::GnuProperty);
+    assert_eq!(segments[4].type_(), PHType::GnuStack);
+    assert!(!segments[4].permissions().is_executable());
+    assert_eq!(segments[5].type_(), PHType::GnuRelro);
+
+    // The property note is 8-byte aligned, so its descriptor follows the name
+    // without padding the header.
+    let note = elf.segment_notes(segments[3]).unwrap().next().unwrap();
+    assert_eq!((note.name, note.type_, note.desc.len()), (&b"GNU"[..], 5, 16));
+    assert_eq!(
+        elf.x86_features().unwrap(),
+        GNU_PROPERTY_X86_FEATURE_1_IBT | GNU_PROPERTY_X86_FEATURE_1_SHSTK
+    );
+
+    let fixture = Fixture::new(EXECUTABLE);
+    let elf = Elf64::from_slice(fixture.bytes()).unwrap();
+    assert_eq!(elf.x86_features().unwrap(), 0);
+}
+
+#[test]
+fn rejects_bad_headers() {
+    let fixture = Fixture::new(&EXECUTABLE[..32]);
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::SliceTooSmall(64))
+    ));
+
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.bytes_mut()[0] = 0;
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::NotElf)
+    ));
+
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.bytes_mut()[4] = 1;
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::NotElf64)
+    ));
+
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.bytes_mut()[6] = 2;
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::InvalidVersion)
+    ));
+}
+
+#[test]
+fn rejects_misaligned_slice() {
+    let mut bytes = vec![0; EXECUTABLE.len() + 1];
+    bytes[1..].copy_from_slice(EXECUTABLE);
+    let fixture = Fixture::new(&bytes);
+    assert!(matches!(
+        Elf64::from_slice(&fixture.bytes()[1..]),
+        Err(Elf64Error::Misaligned)
+    ));
+}
+
+#[test]
+fn rejects_bad_program_header_table() {
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u16(E_PHENTSIZE, 32);
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::BadProgramHeaderSize(32))
+    ));
+
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u64(E_PHOFF, 0x44);
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::BadProgramHeaderTable(0x44))
+    ));
+
+    // The end of the table would wrap around the address space.
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u64(E_PHOFF, !7);
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::BadProgramHeaderTable(_))
+    ));
+
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u16(E_PHNUM, 0xffff);
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::SliceTooSmall(_))
+    ));
+
+    // A file without program headers is valid, if not loadable.
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u16(E_PHNUM, 0);
+    let elf = Elf64::from_slice(fixture.bytes()).unwrap();
+    assert_eq!(elf.program_headers().unwrap().count(), 0);
+}
+
+#[test]
+fn rejects_segments_outside_the_file() {
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u64(P_FILESZ, EXECUTABLE.len() as u64);
+    fixture.set_u64(P_MEMSZ, EXECUTABLE.len() as u64);
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::SegmentOutOfBounds(64))
+    ));
+
+    // The end of the contents would wrap around the address space.
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u64(P_OFFSET, u64::MAX - 8);
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::SegmentOutOfBounds(64))
+    ));
+}
+
+#[test]
+fn rejects_bad_segment_sizes() {
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u64(P_MEMSZ, 8);
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::BadSegmentSize(64))
+    ));
+
+    // The segment would wrap around the address space.
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u64(P_MEMSZ, u64::MAX);
+    assert!(matches!(
+        Elf64::from_slice(fixture.bytes()),
+        Err(Elf64Error::BadSegmentSize(64))
+    ));
+}
+
+#[test]
+fn rejects_bad_section_header_table() {
+    let mut fixture = Fixture::new(EXECUTABLE);
+    fixture.set_u16(E_SHENTSIZE, 32);
+    let elf = Elf64::from_slice(fixture.bytes()).unwrap();
+    assert!(matches!(elf.section_headers(), Err(Elf64Error::BadSection)));
+    assert!(matches!(
+        elf.section_by_name(".text"),
+        Err(Elf64Error::BadSection)
+    ));
+}
+
+#[test]
+fn computes_table_ends() {
+    assert_eq!(table_end(0x40, 3, 56), Some(0x40 + 3 * 56));
+    assert_eq!(table_end(u64::MAX, 1, 56), None);
+    assert_eq!(table_end(0, usize::MAX, 2), None);
+}
+
+#[test]
+fn parses_elf32() {
+    assert!(elf32::Elf32::is_elf32(EXECUTABLE32));
+    assert!(!elf32::Elf32::is_elf32(EXECUTABLE));
+
+    let fixture = Fixture::new(EXECUTABLE32);
+    let elf = elf32::Elf32::from_slice(fixture.bytes()).unwrap();
+    assert!(elf.is_valid_locally());
+    assert_eq!(elf.file_type(), ElfType::Executable);
+    assert_eq!(elf.entry(), BASE32 + 0x1000);
+
+    let segments: Vec<_> = elf.program_headers().unwrap().collect();
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].type_(), PHType::Load);
+    assert_eq!(segments[0].paddr, BASE32 + 0x1000);
+    assert_eq!(segments[0].permissions(), SegmentPermissions::RX);
+    assert_eq!(
+        &elf.segment_data(segments[0]).unwrap()[..3],
+        &[0xf4, 0xeb, 0xfd]
+    );
+    assert_eq!(segments[1].permissions(), SegmentPermissions::RW);
+    assert_eq!(elf.segment_data(segments[1]).unwrap().len(), 8);
+}
+
+#[test]
+fn rejects_bad_elf32_segments() {
+    let fixture = Fixture::new(EXECUTABLE);
+    assert!(matches!(
+        elf32::Elf32::from_slice(fixture.bytes()),
+        Err(elf32::Elf32Error::NotElf32)
+    ));
+
+    let mut fixture = Fixture::new(EXECUTABLE32);
+    fixture.set_u16(E32_PHENTSIZE, 56);
+    assert!(matches!(
+        elf32::Elf32::from_slice(fixture.bytes()),
+        Err(elf32::Elf32Error::BadProgramHeaderSize(56))
+    ));
+
+    let mut fixture = Fixture::new(EXECUTABLE32);
+    fixture.set_u32(P32_FILESZ, EXECUTABLE32.len() as u32);
+    fixture.set_u32(P32_MEMSZ, EXECUTABLE32.len() as u32);
+    assert!(matches!(
+        elf32::Elf32::from_slice(fixture.bytes()),
+        Err(elf32::Elf32Error::SegmentOutOfBounds(52))
+    ));
+
+    // The segment would wrap around the 32-bit physical address space.
+    let mut fixture = Fixture::new(EXECUTABLE32);
+    fixture.set_u32(P32_PADDR, u32::MAX - 8);
+    assert!(matches!(
+        elf32::Elf32::from_slice(fixture.bytes()),
+        Err(elf32::Elf32Error::BadSegmentSize(52))
+    ));
+}
@@ -1,5 +1,7 @@
 // Paging support for x86_64 systems
 
+use core::arch::global_asm;
+use core::arch::asm; // LOCAL-SHIM
 use crate::env;
 
 // The page size used for mappings.
@@ -8,6 +10,25 @@ pub const PAGE_SIZE: usize = 4096;
 // The present bit of a page table entry.
 const PRESENT: u64 = 1;
 
+// The bit of a page table entry allowing writes.
+const WRITABLE: u64 = 1 << 1;
+
+// The bit of a page table entry forbidding instruction fetches.
+const NO_EXECUTE: u64 = 1 << 63;
+
+// The extended feature enable register and its no-execute enable bit.
+const EFER: u32 = 0xc0000080;
+const EFER_NXE: u64 = 1 << 11;
+
+// The control register 4 bit enabling control-flow enforcement (CET).
+const CR4_CET: u64 = 1 << 23;
+
+// The register holding the base address of the FS segment.
+const FS_BASE: u32 = 0xc0000100;
+
+// Whether the no-execute bit is enabled, set by prepare_root_pt().
+static mut NX_ENABLED: bool = false;
+
 // Mask to get a pointed frame from a page table entry.
 const FRAME_MASK: u64 = 0x000ffffffffff000;
 
@@ -69,6 +90,10 @@ pub fn check_page_alignment(addr: usize) -> bool {
 
 // Prepare the root page table.
 pub fn prepare_root_pt() {
+    unsafe {
+        NX_ENABLED = enable_no_execute();
+    }
+
     let ptl4 = get_root_pt();
 
     // Xero the higher half (entries 256-511).
@@ -77,12 +102,26 @@ pub fn prepare_root_pt() {
     }
 }
 
+// Enable the no-execute bit if the processor supports it, return whether it is enabled.
+fn enable_no_execute() -> bool {
+    if cpuid(0x80000000, 0).0 < 0x80000001 || cpuid(0x80000001, 0).3 & (1 << 20) == 0 {
+        return false;
+    }
+    let efer = read_msr(EFER);
+    if efer & EFER_NXE == 0 {
+        write_msr(EFER, efer | EFER_NXE);
+    }
+    true
+}
+
 // Map a page (panics if overwriting a pre-existing mapping).
+// Pages are mapped read-only and non-executable unless requested otherwise;
+// the no-execute bit is left clear if the processor does not support it.
 // Assumptions:
 // 1. The higher-half of the root page table has already been zeroed.
 // 2. efiloader makes absolutely no huge page mappings; all mappings are l1 page table entries.
-// 3. efiloader only sets the PRESENT bit; the kernel will adjust its own mappings later.
-pub fn map(page: usize, addr: usize) {
+// 3. Permissions are only enforced at the l1 level; upper levels allow everything.
+pub fn map(page: usize, addr: usize, writable: bool, executable: bool) {
     assert_eq!(page & 4095, 0, "map requires page aligned addresses");
     assert_eq!(addr & 4095, 0, "map requires page aligned addresses");
     assert!(
@@ -95,7 +134,7 @@ pub fn map(page: usize, addr: usize) {
     let ptl3;
     if ptl4_e == 0 {
         let ptl3_ptr = get_zeroed_pt();
-        ptl4[ptl4_index(addr)] = ptl3_ptr as u64 | PRESENT;
+        ptl4[ptl4_index(addr)] = ptl3_ptr as u64 | PRESENT | WRITABLE;
         ptl3 = get_pt_from_ptr(ptl3_ptr);
     } else {
         ptl3 = get_pt_from_ptr((ptl4_e & FRAME_MASK) as usize)
@@ -105,7 +144,7 @@ pub fn map(page: usize, addr: usize) {
     let ptl2;
     if ptl3_e == 0 {
         let ptl2_ptr = get_zeroed_pt();
-        ptl3[ptl3_index(addr)] = ptl2_ptr as u64 | PRESENT;
+        ptl3[ptl3_index(addr)] = ptl2_ptr as u64 | PRESENT | WRITABLE;
         ptl2 = get_pt_from_ptr(ptl2_ptr);
     } else {
         ptl2 = get_pt_from_ptr((ptl3_e & FRAME_MASK) as usize)
@@ -115,7 +154,7 @@ pub fn map(page: usize, addr: usize) {
     let ptl1;
     if ptl2_e == 0 {
         let ptl1_ptr = get_zeroed_pt();
-        ptl2[ptl2_index(addr)] = ptl1_ptr as u64 | PRESENT;
+        ptl2[ptl2_index(addr)] = ptl1_ptr as u64 | PRESENT | WRITABLE;
         ptl1 = get_pt_from_ptr(ptl1_ptr);
     } else {
         ptl1 = get_pt_from_ptr((ptl2_e & FRAME_MASK) as usize)
@@ -128,5 +167,273 @@ pub fn map(page: usize, addr: usize) {
             addr
         );
     }
-    ptl1[ptl1_index(addr)] = page as u64 | PRESENT;
+    let mut flags = PRESENT;
+    if writable {
+        flags |= WRITABLE;
+    }
+    if !executable && unsafe { NX_ENABLED } {
+        flags |= NO_EXECUTE;
+    }
+    ptl1[ptl1_index(addr)] = page as u64 | flags;
+}
+
+// Get the l1 page table entry mapping an address, if it is mapped.
+// Only works for mappings made by map().
+fn get_ptl1_entry(addr: usize) -> Option<&'static mut u64> {
+    let ptl4_e = get_root_pt()[ptl4_index(addr)];
+    if ptl4_e & PRESENT == 0 {
+        return None;
+    }
+    let ptl3_e = get_pt_from_ptr((ptl4_e & FRAME_MASK) as usize)[ptl3_index(addr)];
+    if ptl3_e & PRESENT == 0 {
+        return None;
+    }
+    let ptl2_e = get_pt_from_ptr((ptl3_e & FRAME_MASK) as usize)[ptl2_index(addr)];
+    if ptl2_e & PRESENT == 0 {
+        return None;
+    }
+    let ptl1_e = &mut get_pt_from_ptr((ptl2_e & FRAME_MASK) as usize)[ptl1_index(addr)];
+    if *ptl1_e & PRESENT == 0 {
+        return None;
+    }
+
+    Some(ptl1_e)
+}
+
+// Get the physical address that an address is mapped to, if it is mapped.
+// Only works for mappings made by map().
+pub fn translate(addr: usize) -> Option<usize> {
+    let ptl1_e = get_ptl1_entry(addr)?;
+    Some((*ptl1_e & FRAME_MASK) as usize + (addr & 4095))
+}
+
+// Get the (writable, executable) permissions of a page mapped by map().
+pub fn permissions(addr: usize) -> Option<(bool, bool)> {
+    let ptl1_e = get_ptl1_entry(addr)?;
+    Some((*ptl1_e & WRITABLE != 0, *ptl1_e & NO_EXECUTE == 0))
+}
+
+// Change the permissions of a page mapped by map().
+pub fn set_permissions(addr: usize, writable: bool, executable: bool) {
+    let ptl1_e = get_ptl1_entry(addr).expect("called set_permissions on an unmapped address");
+    *ptl1_e &= !(WRITABLE | NO_EXECUTE);
+    if writable {
+        *ptl1_e |= WRITABLE;
+    }
+    if !executable && unsafe { NX_ENABLED } {
+        *ptl1_e |= NO_EXECUTE;
+    }
+}
+
+// Execute the cpuid instruction, return (eax, ebx, ecx, edx).
+fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+    unsafe {
+        // rbx is reserved by the compiler, so it is saved around cpuid.
+        asm!(
+            "mov {0:r}, rbx",
+            "cpuid",
+            "xchg {0:r}, rbx",
+            out(reg) ebx,
+            inout("eax") leaf => eax,
+            inout("ecx") subleaf => ecx,
+            out("edx") edx,
+        );
+    }
+    (eax, ebx, ecx, edx)
+}
+
+// Read a model specific register.
+fn read_msr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high);
+    }
+    (high as u64) << 32 | low as u64
+}
+
+// Write a model specific register.
+fn write_msr(msr: u32, value: u64) {
+    unsafe {
+        asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32);
+    }
+}
+
+// Set the thread pointer, which is the FS segment base on x86_64.
+pub fn set_thread_pointer(addr: usize) {
+    write_msr(FS_BASE, addr as u64);
+}
+
+// Turn off control-flow enforcement if the firmware enabled it, so that kernels
+// are entered without indirect branch tracking or shadow stacks, whatever their
+// markings. Kernels marked as compatible can enable them.
+pub fn disable_cet() {
+    let cr4: u64;
+    unsafe {
+        asm!("mov {0}, cr4", out(reg) cr4);
+        if cr4 & CR4_CET != 0 {
+            asm!("mov cr4, {0}", in(reg) cr4 & !CR4_CET);
+        }
+    }
+}
+
+// Get a random number from the RDSEED or RDRAND instructions, if the processor
+// supports them and they succeed within a few retries.
+pub fn hardware_random() -> Option<u64> {
+    let max_leaf = cpuid(0, 0).0;
+    let has_rdrand = cpuid(1, 0).2 & (1 << 30) != 0;
+    let has_rdseed = max_leaf >= 7 && cpuid(7, 0).1 & (1 << 18) != 0;
+
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        if has_rdseed {
+            unsafe { asm!("rdseed {0}", "setc {1}", out(reg) value, out(reg_byte) ok) };
+        } else if has_rdrand {
+            unsafe { asm!("rdrand {0}", "setc {1}", out(reg) value, out(reg_byte) ok) };
+        } else {
+            return None;
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+// Read the timestamp counter.
+pub fn timestamp() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high);
+    }
+    (high as u64) << 32 | low as u64
+}
+
+// Call the kernel's entry function with the sysv64 calling convention on a new stack.
+pub fn enter_kernel(entry: usize, stack_top: usize, magic: u64, info: usize) -> ! {
+    unsafe {
+        asm!(
+            "mov rsp, {0}",
+            "xor ebp, ebp",
+            "call {1}",
+            // The kernel should never return.
+            "2:",
+            "hlt",
+            "jmp 2b",
+            in(reg) stack_top,
+            in(reg) entry,
+            in("rdi") magic,
+            in("rsi") info,
+            options(noreturn),
+        );
+    }
+}
+
+// The trampoline that enters 32-bit kernels. It is copied below 4 GiB, where
+// memory is identity mapped, and jumped to with the kernel's entry point in
+// rsi, the boot information in rdx and the top of a stack below 4 GiB in rcx.
+// It switches to a flat 32-bit code segment, turns off paging and long mode,
+// and calls the entry point with the cdecl convention as
+// `fn(magic: u64, info: u32)`. The low half of the magic number is also in eax
+// and the boot information in ebx.
+global_asm!(
+    ".global uefi_boot_trampoline_start",
+    ".global uefi_boot_trampoline_end",
+    ".code64",
+    "uefi_boot_trampoline_start:",
+    "cli",
+    "mov rsp, rcx",
+    // Paging cannot be turned off with PCIDE set, and PCIDE can only be cleared
+    // with a PCID of zero in CR3.
+    "mov rax, cr3",
+    "and rax, ~0xfff",
+    "mov cr3, rax",
+    "mov rax, cr4",
+    "and rax, ~0x20000",
+    "mov cr4, rax",
+    // Load a GDT with flat 32-bit code (0x08) and data (0x10) segments.
+    "lea rax, [rip + uefi_boot_trampoline_gdt]",
+    "sub rsp, 16",
+    "mov word ptr [rsp], 23",
+    "mov qword ptr [rsp + 2], rax",
+    "lgdt [rsp]",
+    "add rsp, 16",
+    // Keep the entry point and boot information in registers kept by the switch.
+    "mov ebp, esi",
+    "mov ebx, edx",
+    // Far return to the 32-bit code segment.
+    "lea rax, [rip + uefi_boot_trampoline_32]",
+    "push 0x08",
+    "push rax",
+    "retfq",
+    ".code32",
+    "uefi_boot_trampoline_32:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov ss, ax",
+    // Turn off paging, then long mode and PAE.
+    "mov eax, cr0",
+    "and eax, 0x7fffffff",
+    "mov cr0, eax",
+    "jmp uefi_boot_trampoline_paging_off",
+    "uefi_boot_trampoline_paging_off:",
+    "mov ecx, 0xc0000080",
+    "rdmsr",
+    "and eax, 0xfffffeff",
+    "wrmsr",
+    "mov eax, cr4",
+    "and eax, 0xffffffdf",
+    "mov cr4, eax",
+    // Call the entry point with the magic number.
+    "push ebx",
+    "push {magic_high}",
+    "push {magic_low}",
+    "mov eax, {magic_low}",
+    "call ebp",
+    // The kernel should never return.
+    "uefi_boot_trampoline_halt:",
+    "hlt",
+    "jmp uefi_boot_trampoline_halt",
+    ".p2align 3",
+    "uefi_boot_trampoline_gdt:",
+    ".quad 0",
+    ".quad 0x00cf9a000000ffff",
+    ".quad 0x00cf92000000ffff",
+    "uefi_boot_trampoline_end:",
+    ".code64",
+    magic_high = const crate::interface::MAGIC >> 32,
+    magic_low = const crate::interface::MAGIC & 0xffffffff,
+);
+
+extern "C" {
+    static uefi_boot_trampoline_start: u8;
+    static uefi_boot_trampoline_end: u8;
+}
+
+// Get the code of the trampoline that enters 32-bit kernels.
+pub fn protected_mode_trampoline() -> &'static [u8] {
+    unsafe {
+        let start = &uefi_boot_trampoline_start as *const u8;
+        let end = &uefi_boot_trampoline_end as *const u8;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+// Enter a 32-bit kernel through a copy of the trampoline below 4 GiB.
+pub fn enter_protected_mode(trampoline: usize, entry: usize, stack_top: usize, info: usize) -> ! {
+    unsafe {
+        asm!(
+            "jmp {0}",
+            in(reg) trampoline,
+            in("rsi") entry,
+            in("rdx") info,
+            in("rcx") stack_top,
+            options(noreturn),
+        );
+    }
 }
@@ -0,0 +1,371 @@
+// Boot configuration file
+//
+// The configuration file is a plain-text file named `uefi-boot.conf` that lives
+// in the same directory as the loader image. Each line holds a key and a value
+// separated by whitespace. Blank lines are ignored and `#` starts a comment
+// when it begins the line or follows whitespace, so `foo=#1` keeps its `#`.
+//
+//     resolution  1920x1080
+//     timeout     5
+//     default     release
+//     ramdisk     uefi-boot\init.rd
+//
+//     entry release
+//         kernel  uefi-boot\release.elf64
+//         module  uefi-boot\console.elf64 console tty=0
+//         module  uefi-boot\fs.elf64
+//         cmdline loglevel=info
+//         fallback debug
+//
+//     entry debug
+//         kernel  uefi-boot\debug.elf64
+//         cmdline loglevel=debug
+//
+// An `entry` line starts a named boot entry, and the `kernel`, `ramdisk`,
+// `module` and `cmdline` keys that follow it apply to that entry. The same keys
+// given before the first entry are defaults inherited by every entry; if there
+// are no entries, they describe a single entry named `default`.
+//
+// Each `module` line adds a boot module: a path, optionally followed by a
+// string passed to the kernel with the module. The string defaults to the path.
+//
+// If an entry fails to load, the entry named by its `fallback` key is tried
+// instead, until an entry loads or there is no untried fallback left.
+//
+// Position-independent kernels are loaded at the address given by an entry's
+// `base` key, or at the top 2 GiB of the address space by default. With
+// `kaslr virtual`, they are loaded at a random base in the top 512 GiB instead;
+// `kaslr full` also places its segments at random physical addresses, which works
+// for kernels that are not position-independent too. `kaslr off` is the default.
+//
+// An entry with a `tries` key uses boot counting: each boot of the entry uses
+// up one try, and once none are left the entry is skipped in favour of its
+// fallback. Two entries that name each other as fallbacks form A/B slots.
+//
+// The ramdisk is optional. Without a configuration file, `uefi-boot\init.rd`
+// is loaded if it exists; with one, only a ramdisk named by `ramdisk` is, and
+// the entry fails to load if that ramdisk is missing.
+//
+// With several entries, a menu is shown. `timeout` gives the number of seconds
+// after which the default entry is booted if no key is pressed; without it, the
+// menu waits forever. The default entry is the last booted one, falling back to
+// the one named by `default` and then to the first entry.
+//
+// Paths are relative to the root of the volume the loader was loaded from.
+// Lines that cannot be parsed are reported with their line number and skipped.
+
+use crate::env;
+use crate::loader::Kaslr;
+
+// The name of the configuration file.
+const CONFIG_NAME: &str = "uefi-boot.conf";
+
+// Paths used when the configuration file does not name them.
+const DEFAULT_KERNEL_PATH: &str = "uefi-boot\\kernel.elf64";
+const DEFAULT_RAMDISK_PATH: &str = "uefi-boot\\init.rd";
+
+// The maximum number of boot entries.
+const MAX_ENTRIES: usize = 16;
+
+// The maximum number of modules per boot entry.
+const MAX_MODULES: usize = 32;
+
+// A boot module.
+#[derive(Clone, Copy)]
+pub struct Module {
+    // Path to the module file.
+    pub path: &'static str,
+    // String passed to the kernel with the module.
+    pub cmdline: &'static str,
+}
+
+// A boot entry. Strings point into the file buffer, which is never freed.
+#[derive(Clone, Copy)]
+pub struct Entry {
+    // Name shown in the boot menu.
+    pub name: &'static str,
+    // Path to the kernel executable.
+    pub kernel: &'static str,
+    // Path to the ramdisk, if any.
+    pub ramdisk: Option<&'static str>,
+    // Whether the ramdisk was configured, so that the entry fails to load
+    // without it, rather than being the optional default one.
+    pub ramdisk_required: bool,
+    // Command line for the kernel.
+    pub cmdline: &'static str,
+    // Index of the entry to try if this one fails to load.
+    pub fallback: Option<usize>,
+    // Number of boot tries before falling back, if boot counting is used.
+    pub tries: Option<u32>,
+    // Load base for position-independent kernels.
+    pub base: Option<u64>,
+    // How the kernel's load address is randomized.
+    pub kaslr: Kaslr,
+    fallback_name: Option<&'static str>,
+    modules: [Module; MAX_MODULES],
+    n_modules: usize,
+}
+
+impl Entry {
+    // Get the boot modules.
+    pub fn modules(&self) -> &[Module] {
+        &self.modules[..self.n_modules]
+    }
+
+    // Get the boot modules for modification.
+    pub fn modules_mut(&mut self) -> &mut [Module] {
+        &mut self.modules[..self.n_modules]
+    }
+
+    // Add a boot module from a path and an optional string.
+    fn add_module(&mut self, value: &'static str) -> Result<(), &'static str> {
+        let (path, cmdline) = match value.find(char::is_whitespace) {
+            Some(x) => (&value[..x], value[x..].trim_start()),
+            None => (value, value),
+        };
+        let path = parse_path(path)?;
+        if self.n_modules == MAX_MODULES {
+            return Err("too many modules");
+        }
+        self.modules[self.n_modules] = Module { path, cmdline };
+        self.n_modules += 1;
+        Ok(())
+    }
+}
+
+// The boot configuration.
+pub struct Config {
+    // Preferred graphics resolution (horizontal, vertical).
+    pub resolution: Option<(u32, u32)>,
+    // Seconds to wait in the menu before booting the default entry.
+    pub timeout: Option<u64>,
+    // Index of the configured default entry.
+    pub default: usize,
+    default_name: Option<&'static str>,
+    // Values inherited by entries.
+    defaults: Entry,
+    entries: [Entry; MAX_ENTRIES],
+    n_entries: usize,
+}
+
+impl Config {
+    // The configuration used when there is no configuration file.
+    fn default() -> Config {
+        let defaults = Entry {
+            name: "default",
+            kernel: DEFAULT_KERNEL_PATH,
+            ramdisk: Some(DEFAULT_RAMDISK_PATH),
+            ramdisk_required: false,
+            cmdline: "",
+            fallback: None,
+            fallback_name: None,
+            tries: None,
+            base: None,
+            kaslr: Kaslr::Off,
+            modules: [Module { path: "", cmdline: "" }; MAX_MODULES],
+            n_modules: 0,
+        };
+        Config {
+            resolution: None,
+            timeout: None,
+            default: 0,
+            default_name: None,
+            defaults,
+            entries: [defaults; MAX_ENTRIES],
+            n_entries: 0,
+        }
+    }
+
+    // Get the boot entries.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries[..self.n_entries]
+    }
+
+    // Get the boot entries for modification.
+    pub fn entries_mut(&mut self) -> &mut [Entry] {
+        &mut self.entries[..self.n_entries]
+    }
+
+    // Get the entry that keys currently apply to.
+    fn current(&mut self) -> &mut Entry {
+        match self.n_entries {
+            0 => &mut self.defaults,
+            n => &mut self.entries[n - 1],
+        }
+    }
+
+    // Start a new entry inheriting the defaults.
+    fn add_entry(&mut self, name: &'static str) -> Result<(), &'static str> {
+        let name = parse_name(name)?;
+        if self.entries().iter().any(|e| e.name == name) {
+            return Err("duplicate entry name");
+        }
+        if self.n_entries == MAX_ENTRIES {
+            return Err("too many entries");
+        }
+        self.entries[self.n_entries] = Entry { name, ..self.defaults };
+        self.n_entries += 1;
+        Ok(())
+    }
+
+    // Make sure there is at least one entry and resolve the default and
+    // fallback entries once parsing is done.
+    fn finish(mut self) -> Config {
+        if self.n_entries == 0 {
+            self.entries[0] = self.defaults;
+            self.n_entries = 1;
+        }
+        if let Some(name) = self.default_name {
+            match self.entries().iter().position(|e| e.name == name) {
+                Some(x) => self.default = x,
+                None => println!("ERROR: {}: default entry {} does not exist", CONFIG_NAME, name),
+            }
+        }
+        for x in 0..self.n_entries {
+            if let Some(name) = self.entries[x].fallback_name {
+                let fallback = self.entries().iter().position(|e| e.name == name);
+                if fallback.is_none() {
+                    println!("ERROR: {}: fallback entry {} does not exist", CONFIG_NAME, name);
+                }
+                self.entries[x].fallback = fallback;
+            }
+        }
+        self
+    }
+}
+
+// Load the configuration file, falling back to the defaults if it is missing.
+pub fn load() -> Config {
+    let mut config = Config::default();
+
+    let file = match env::open_sibling_file(CONFIG_NAME) {
+        Some(file) => file,
+        None => {
+            println!("no {} found, using default paths", CONFIG_NAME);
+            return config.finish();
+        }
+    };
+    let (start, length) = match env::read_file(file) {
+        Some(contents) => contents,
+        None => {
+            println!("ERROR: failed to read {}, using default paths", CONFIG_NAME);
+            return config.finish();
+        }
+    };
+    let bytes: &'static [u8] = unsafe { core::slice::from_raw_parts(start as *const u8, length) };
+
+    // The default ramdisk path only applies when there is no configuration file.
+    config.defaults.ramdisk = None;
+
+    // Parse everything up to the first line that is not valid UTF-8.
+    let text = match core::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => {
+            let valid = &bytes[..e.valid_up_to()];
+            let line = valid.iter().filter(|&&b| b == b'\n').count() + 1;
+            println!("ERROR: {} line {}: invalid UTF-8, ignoring the rest of the file", CONFIG_NAME, line);
+            let end = valid.iter().rposition(|&b| b == b'\n').map_or(0, |x| x + 1);
+            // Safe because the slice ends before the first invalid byte.
+            unsafe { core::str::from_utf8_unchecked(&valid[..end]) }
+        }
+    };
+
+    for (n, line) in text.lines().enumerate() {
+        if let Err(msg) = parse_line(&mut config, line) {
+            println!("ERROR: {} line {}: {}", CONFIG_NAME, n + 1, msg);
+        }
+    }
+
+    config.finish()
+}
+
+// Parse a single line of the configuration file into the configuration.
+fn parse_line(config: &mut Config, line: &'static str) -> Result<(), &'static str> {
+    // Strip comments and surrounding whitespace. A `#` only starts a comment at
+    // the start of the line or after whitespace, so values may contain one.
+    let comment = line
+        .char_indices()
+        .find(|&(x, c)| c == '#' && (x == 0 || line[..x].ends_with(char::is_whitespace)));
+    let line = match comment {
+        Some((x, _)) => &line[..x],
+        None => line,
+    };
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    // Split the line into a key and a value.
+    let (key, value) = match line.find(char::is_whitespace) {
+        Some(x) => (&line[..x], line[x..].trim_start()),
+        None => (line, ""),
+    };
+
+    match key {
+        "entry" => config.add_entry(value)?,
+        "kernel" => config.current().kernel = parse_path(value)?,
+        "ramdisk" => {
+            let entry = config.current();
+            entry.ramdisk = Some(parse_path(value)?);
+            entry.ramdisk_required = true;
+        }
+        "module" => config.current().add_module(value)?,
+        "cmdline" => config.current().cmdline = value,
+        "fallback" => config.current().fallback_name = Some(parse_name(value)?),
+        "tries" => config.current().tries = Some(value.parse().map_err(|_| "expected a number of tries")?),
+        "base" => config.current().base = Some(parse_address(value)?),
+        "kaslr" => config.current().kaslr = parse_kaslr(value)?,
+        "resolution" => config.resolution = Some(parse_resolution(value)?),
+        "timeout" => config.timeout = Some(value.parse().map_err(|_| "expected a number of seconds")?),
+        "default" => config.default_name = Some(parse_name(value)?),
+        _ => return Err("unknown key"),
+    }
+
+    Ok(())
+}
+
+// Parse an entry name value.
+fn parse_name(value: &'static str) -> Result<&'static str, &'static str> {
+    if value.is_empty() {
+        Err("expected an entry name")
+    } else {
+        Ok(value)
+    }
+}
+
+// Parse a path value.
+fn parse_path(value: &'static str) -> Result<&'static str, &'static str> {
+    if value.is_empty() {
+        Err("expected a path")
+    } else {
+        Ok(value)
+    }
+}
+
+// Parse a hexadecimal address value of the form `0x<digits>`.
+fn parse_address(value: &str) -> Result<u64, &'static str> {
+    const ERROR: &str = "expected a hexadecimal address such as 0xffffffff80000000";
+    if !value.starts_with("0x") {
+        return Err(ERROR);
+    }
+    u64::from_str_radix(&value[2..], 16).map_err(|_| ERROR)
+}
+
+// Parse a load address randomization mode.
+fn parse_kaslr(value: &str) -> Result<Kaslr, &'static str> {
+    match value {
+        "off" => Ok(Kaslr::Off),
+        "virtual" => Ok(Kaslr::Virtual),
+        "full" => Ok(Kaslr::Full),
+        _ => Err("expected off, virtual or full"),
+    }
+}
+
+// Parse a resolution value of the form `<horizontal>x<vertical>`.
+fn parse_resolution(value: &str) -> Result<(u32, u32), &'static str> {
+    const ERROR: &str = "expected a resolution such as 1920x1080";
+    let x = value.find('x').ok_or(ERROR)?;
+    let h_res = value[..x].parse().map_err(|_| ERROR)?;
+    let v_res = value[x + 1..].parse().map_err(|_| ERROR)?;
+    Ok((h_res, v_res))
+}
@@ -0,0 +1,94 @@
+// Line editor on the UEFI console
+
+use crate::env;
+
+// The maximum length of an edited line in UTF-16 code units.
+const LINE_MAX: usize = 512;
+
+// Scan codes for the keys used by the editor.
+const SCAN_RIGHT: u16 = 0x03;
+const SCAN_LEFT: u16 = 0x04;
+const SCAN_HOME: u16 = 0x05;
+const SCAN_END: u16 = 0x06;
+const SCAN_DELETE: u16 = 0x08;
+const SCAN_ESC: u16 = 0x17;
+
+// Characters produced by the backspace and enter keys.
+const CHAR_BACKSPACE: u16 = 0x08;
+const CHAR_CARRIAGE_RETURN: u16 = 0x0d;
+
+// Edit a line of text on the console, starting from an initial value.
+// Returns the edited text, or None if editing was cancelled with escape.
+// The text lives in pool memory for the rest of the boot.
+pub fn edit(prompt: &str, initial: &str) -> Option<&'static str> {
+    let mut line = [0u16; LINE_MAX];
+    let mut length = 0;
+    for c in initial.encode_utf16() {
+        if length == LINE_MAX {
+            println!("WARNING: line is too long to edit, truncating it");
+            break;
+        }
+        line[length] = c;
+        length += 1;
+    }
+
+    let mut cursor = length;
+    let row = env::cursor_row();
+    let prompt_length = prompt.chars().count();
+    loop {
+        // Redraw the line, with a trailing space to erase a deleted character.
+        env::set_cursor(0, row);
+        print!("{}", prompt);
+        for c in char::decode_utf16(line[..length].iter().cloned()) {
+            print!("{}", c.unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+        print!(" ");
+        env::set_cursor(prompt_length + cursor, row);
+
+        let key = env::read_key();
+        match (key.scan_code, key.unicode_char) {
+            (SCAN_LEFT, _) => cursor = cursor.saturating_sub(1),
+            (SCAN_RIGHT, _) => cursor = (cursor + 1).min(length),
+            (SCAN_HOME, _) => cursor = 0,
+            (SCAN_END, _) => cursor = length,
+            (SCAN_DELETE, _) if cursor < length => {
+                line.copy_within(cursor + 1..length, cursor);
+                length -= 1;
+            }
+            (SCAN_ESC, _) => {
+                println!();
+                return None;
+            }
+            (_, CHAR_CARRIAGE_RETURN) => break,
+            (_, CHAR_BACKSPACE) if cursor > 0 => {
+                line.copy_within(cursor..length, cursor - 1);
+                length -= 1;
+                cursor -= 1;
+            }
+            (_, c) if c >= 0x20 && length < LINE_MAX => {
+                line.copy_within(cursor..length, cursor + 1);
+                line[cursor] = c;
+                length += 1;
+                cursor += 1;
+            }
+            _ => {}
+        }
+    }
+    println!();
+
+    // Convert the line to UTF-8.
+    let chars = || {
+        char::decode_utf16(line[..length].iter().cloned())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    };
+    let size = chars().map(char::len_utf8).sum::<usize>();
+    let start = env::allocate_pool(size.max(1)).expect("failed to allocate buffer for an edited line");
+    let buffer = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, size) };
+    let mut n = 0;
+    for c in chars() {
+        n += c.encode_utf8(&mut buffer[n..]).len();
+    }
+
+    // Safe because only encoded characters were written to the buffer.
+    Some(unsafe { core::str::from_utf8_unchecked(buffer) })
+}
@@ -1,8 +1,12 @@
 // Functions to interact with the UEFI boot services environment
 
-use crate::{ROOT, ST};
+use crate::{arch, IMAGE, ROOT, ST};
 use core::fmt::{self, Write};
 use r_efi::efi;
+use r_efi::efi::protocols::{device_path, file};
+
+// The maximum length of a file path in UTF-16 code units, including the NUL.
+const PATH_MAX: usize = 512;
 
 // A struct representing the EFI console for print! and println!
 // NOTE: Because write_str invokes the EFI for each individual character, it
@@ -48,6 +52,168 @@ macro_rules! println {
     ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
 }
 
+// Console attributes for normal and highlighted text.
+pub const ATTR_NORMAL: usize = 0x07;
+pub const ATTR_HIGHLIGHT: usize = 0x70;
+
+// Clear the console screen.
+pub fn clear_screen() {
+    let _ = unsafe { ((*(*ST).con_out).clear_screen)((*ST).con_out) };
+}
+
+// Move the console cursor.
+pub fn set_cursor(column: usize, row: usize) {
+    let _ = unsafe { ((*(*ST).con_out).set_cursor_position)((*ST).con_out, column, row) };
+}
+
+// Get the row the console cursor is on.
+pub fn cursor_row() -> usize {
+    unsafe { (*(*(*ST).con_out).mode).cursor_row as usize }
+}
+
+// Set the attribute used for console output.
+pub fn set_attribute(attribute: usize) {
+    let _ = unsafe { ((*(*ST).con_out).set_attribute)((*ST).con_out, attribute) };
+}
+
+// Wait for a key press on the console and return it.
+pub fn read_key() -> efi::protocols::simple_text_input::InputKey {
+    loop {
+        if let Some(key) = read_key_or(unsafe { (*(*ST).con_in).wait_for_key }) {
+            return key;
+        }
+    }
+}
+
+// Get a key that was already pressed on the console, without waiting.
+pub fn poll_key() -> Option<efi::protocols::simple_text_input::InputKey> {
+    let mut key = efi::protocols::simple_text_input::InputKey {
+        scan_code: 0,
+        unicode_char: 0,
+    };
+    let status = unsafe { ((*(*ST).con_in).read_key_stroke)((*ST).con_in, &mut key) };
+    if status.is_error() {
+        None
+    } else {
+        Some(key)
+    }
+}
+
+// Wait for a key press on the console or for an event to be signalled.
+// Returns None if the event was signalled first.
+pub fn read_key_or(event: efi::Event) -> Option<efi::protocols::simple_text_input::InputKey> {
+    let mut key = efi::protocols::simple_text_input::InputKey {
+        scan_code: 0,
+        unicode_char: 0,
+    };
+    loop {
+        let mut events = [unsafe { (*(*ST).con_in).wait_for_key }, event];
+        let mut index = 0usize;
+        let _ = unsafe { ((*(*ST).boot_services).wait_for_event)(2, events.as_mut_ptr(), &mut index) };
+        if index == 1 {
+            return None;
+        }
+        let status = unsafe { ((*(*ST).con_in).read_key_stroke)((*ST).con_in, &mut key) };
+        if !status.is_error() {
+            return Some(key);
+        }
+    }
+}
+
+// Create a timer event that is signalled once per second.
+pub fn create_seconds_timer() -> Option<efi::Event> {
+    let mut event = 0 as efi::Event;
+    let status = unsafe {
+        ((*(*ST).boot_services).create_event)(
+            efi::EVT_TIMER,
+            efi::TPL_APPLICATION,
+            None,
+            0 as *mut core::ffi::c_void,
+            &mut event,
+        )
+    };
+    if status.is_error() {
+        return None;
+    }
+
+    // The timer period is given in units of 100ns.
+    let status =
+        unsafe { ((*(*ST).boot_services).set_timer)(event, efi::TIMER_PERIODIC, 10_000_000) };
+    if status.is_error() {
+        close_event(event);
+        None
+    } else {
+        Some(event)
+    }
+}
+
+// Close an event.
+pub fn close_event(event: efi::Event) {
+    let _ = unsafe { ((*(*ST).boot_services).close_event)(event) };
+}
+
+// The code logged when the watchdog timer set by uefi-boot expires. The
+// firmware reserves the codes up to 0xffff.
+const WATCHDOG_CODE: u64 = 0x10000;
+
+// Set the watchdog timer, which resets the machine once it expires, to a
+// number of seconds. Zero disables it.
+pub fn set_watchdog_timer(seconds: usize) {
+    let _ = unsafe {
+        ((*(*ST).boot_services).set_watchdog_timer)(seconds, WATCHDOG_CODE, 0, 0 as *mut u16)
+    };
+}
+
+// The vendor GUID of uefi-boot's UEFI variables, 274337e7-54a0-4993-b176-ab29cb3f7b43.
+const VENDOR_GUID: efi::Guid = efi::Guid::from_fields(
+    0x274337e7,
+    0x54a0,
+    0x4993,
+    0xb1,
+    0x76,
+    &[0xab, 0x29, 0xcb, 0x3f, 0x7b, 0x43],
+);
+
+// Read one of uefi-boot's UEFI variables into a buffer, return its length in bytes.
+pub fn get_variable(name: &[u16], buffer: &mut [u8]) -> Option<usize> {
+    let mut guid = VENDOR_GUID;
+    let mut size = buffer.len();
+    let status = unsafe {
+        ((*(*ST).runtime_services).get_variable)(
+            name.as_ptr() as *mut efi::Char16,
+            &mut guid,
+            0 as *mut u32,
+            &mut size,
+            buffer.as_mut_ptr() as *mut core::ffi::c_void,
+        )
+    };
+    if status.is_error() {
+        None
+    } else {
+        Some(size)
+    }
+}
+
+// Write one of uefi-boot's UEFI variables to non-volatile storage.
+// Writing an empty buffer deletes the variable.
+pub fn set_variable(name: &[u16], data: &[u8]) {
+    let mut guid = VENDOR_GUID;
+    let status = unsafe {
+        ((*(*ST).runtime_services).set_variable)(
+            name.as_ptr() as *mut efi::Char16,
+            &mut guid,
+            efi::VARIABLE_NON_VOLATILE
+                | efi::VARIABLE_BOOTSERVICE_ACCESS
+                | efi::VARIABLE_RUNTIME_ACCESS,
+            data.len(),
+            data.as_ptr() as *mut core::ffi::c_void,
+        )
+    };
+    if status.is_error() && !(data.is_empty() && status == efi::Status::NOT_FOUND) {
+        println!("WARNING: set_variable {:?}", status);
+    }
+}
+
 // Initialize the filesystem.
 pub fn init_fs(image_handle: efi::Handle) {
     // Open the loaded image protocol.
@@ -66,6 +232,9 @@ pub fn init_fs(image_handle: efi::Handle) {
     if status.is_error() {
         panic!("open_protocol: loaded image protocol {:?}", status);
     }
+    unsafe {
+        IMAGE = loaded_image_p;
+    }
 
     // Open the simple file system protocol on the device that efiloader was loaded from.
     let mut file_system_p = 0 as *mut efi::protocols::simple_file_system::Protocol;
@@ -96,6 +265,20 @@ pub fn init_fs(image_handle: efi::Handle) {
     }
 }
 
+// Get the load options of the loader image as UTF-16, up to the first NUL.
+pub fn load_options() -> &'static [u16] {
+    let (ptr, size) = unsafe { ((*IMAGE).load_options, (*IMAGE).load_options_size as usize) };
+    if ptr.is_null() || !size.is_multiple_of(2) || !(ptr as usize).is_multiple_of(2) {
+        return &[];
+    }
+
+    let options = unsafe { core::slice::from_raw_parts(ptr as *const u16, size / 2) };
+    match options.iter().position(|&c| c == 0) {
+        Some(x) => &options[..x],
+        None => options,
+    }
+}
+
 // Allocate memory from the pool.
 pub fn allocate_pool(s: usize) -> Option<usize> {
     let mut ptr = 0 as *mut core::ffi::c_void;
@@ -139,6 +322,104 @@ pub fn allocate_pages(n: usize) -> Option<usize> {
     }
 }
 
+// Allocate physical pages at a random 2M aligned address in free memory, using
+// the given random number to pick among the possible addresses.
+pub fn allocate_pages_random(n: usize, random: u64) -> Option<usize> {
+    const ALIGN: u64 = 0x200000;
+    let size = (n * arch::PAGE_SIZE) as u64;
+
+    // Count the aligned slots of each free region, then find the chosen one.
+    let ((mmap, mmap_length, desc_size), _) = crate::get_memory_map(false);
+    let regions = || {
+        (0..mmap_length / desc_size)
+            .map(move |x| unsafe { &*((mmap + x * desc_size) as *const efi::MemoryDescriptor) })
+            .filter(|d| d.r#type == efi::CONVENTIONAL_MEMORY)
+            .filter_map(move |d| {
+                // Address zero is never used, so the first slot starts at ALIGN.
+                let first = ((d.physical_start + ALIGN - 1) & !(ALIGN - 1)).max(ALIGN);
+                let end = d.physical_start + d.number_of_pages * arch::PAGE_SIZE as u64;
+                if first + size > end {
+                    None
+                } else {
+                    Some((first, (end - size - first) / ALIGN + 1))
+                }
+            })
+    };
+    let n_slots: u64 = regions().map(|(_, slots)| slots).sum();
+    let mut address = None;
+    if n_slots != 0 {
+        let mut slot = random % n_slots;
+        for (first, slots) in regions() {
+            if slot < slots {
+                address = Some(first + slot * ALIGN);
+                break;
+            }
+            slot -= slots;
+        }
+    }
+    free_pool(mmap);
+
+    let address = match address {
+        Some(address) => address as usize,
+        None => {
+            println!("WARNING: no free memory to randomize placement, using any address");
+            return allocate_pages(n);
+        }
+    };
+    match allocate_pages_at(address, n, false) {
+        Some(page) => Some(page),
+        None => {
+            println!("WARNING: failed to allocate pages at {:#x}, using any address", address);
+            allocate_pages(n)
+        }
+    }
+}
+
+// Allocate physical pages at a given address. Executable pages are allocated as
+// loader code, which the firmware does not map as non-executable.
+pub fn allocate_pages_at(address: usize, n: usize, executable: bool) -> Option<usize> {
+    let mut page = address as efi::PhysicalAddress;
+    let status = unsafe {
+        ((*(*ST).boot_services).allocate_pages)(
+            efi::ALLOCATE_ADDRESS,
+            if executable { efi::LOADER_CODE } else { efi::LOADER_DATA },
+            n,
+            &mut page,
+        )
+    };
+    if status.is_error() {
+        None
+    } else {
+        Some(page as usize)
+    }
+}
+
+// Allocate physical pages below 4 GiB, for data that 32-bit kernels must reach.
+pub fn allocate_low_pages(n: usize, executable: bool) -> Option<usize> {
+    let mut page: efi::PhysicalAddress = 0xffffffff;
+    let status = unsafe {
+        ((*(*ST).boot_services).allocate_pages)(
+            efi::ALLOCATE_MAX_ADDRESS,
+            if executable { efi::LOADER_CODE } else { efi::LOADER_DATA },
+            n,
+            &mut page,
+        )
+    };
+    if status.is_error() {
+        None
+    } else {
+        Some(page as usize)
+    }
+}
+
+// Free physical pages.
+pub fn free_pages(page: usize, n: usize) {
+    let status = unsafe { ((*(*ST).boot_services).free_pages)(page as efi::PhysicalAddress, n) };
+    if status.is_error() {
+        panic!("called free_pages() on invalid pages: {}", page);
+    }
+}
+
 // Open a file in read-only mode.
 pub fn open_file(path: *mut u16) -> Option<*mut efi::protocols::file::Protocol> {
     let mut file = 0 as *mut efi::protocols::file::Protocol;
@@ -158,3 +439,139 @@ pub fn open_file(path: *mut u16) -> Option<*mut efi::protocols::file::Protocol>
         Some(file)
     }
 }
+
+// Open a file given a UTF-8 path relative to the root of the filesystem.
+pub fn open_path(path: &str) -> Option<*mut efi::protocols::file::Protocol> {
+    let mut buffer = [0u16; PATH_MAX];
+    let n = match encode_path(&mut buffer, 0, path) {
+        Some(n) => n,
+        None => {
+            println!("ERROR: path too long: {}", path);
+            return None;
+        }
+    };
+    buffer[n] = 0;
+    open_file(buffer.as_mut_ptr())
+}
+
+// Open a file in the same directory as the loader image.
+pub fn open_sibling_file(name: &str) -> Option<*mut efi::protocols::file::Protocol> {
+    let mut buffer = [0u16; PATH_MAX];
+    let mut n = 0;
+
+    // Collect the file path nodes of the loaded image's device path.
+    let mut node = unsafe { (*IMAGE).file_path };
+    while !node.is_null() {
+        let header = unsafe { core::ptr::read_unaligned(node) };
+        let length = u16::from_le_bytes(header.length) as usize;
+        if header.r#type == device_path::TYPE_END || length < 4 {
+            break;
+        }
+        if header.r#type == device_path::TYPE_MEDIA
+            && header.sub_type == device_path::Media::SUBTYPE_FILE_PATH
+        {
+            // The node holds a NUL-terminated UTF-16 path, possibly unaligned.
+            let chars = (node as usize + 4) as *const u16;
+            for x in 0..(length - 4) / 2 {
+                let c = unsafe { core::ptr::read_unaligned(chars.add(x)) };
+                if c == 0 {
+                    break;
+                }
+                if n == PATH_MAX {
+                    return None;
+                }
+                buffer[n] = c;
+                n += 1;
+            }
+        }
+        node = (node as usize + length) as *mut device_path::Protocol;
+    }
+
+    // Strip the file name of the image, keeping the trailing separator.
+    while n > 0 && buffer[n - 1] != '\\' as u16 {
+        n -= 1;
+    }
+
+    let n = match encode_path(&mut buffer, n, name) {
+        Some(n) => n,
+        None => {
+            println!("ERROR: path too long: {}", name);
+            return None;
+        }
+    };
+    buffer[n] = 0;
+    open_file(buffer.as_mut_ptr())
+}
+
+// Encode a UTF-8 path as UTF-16 into a buffer starting at an index, converting
+// forward slashes to backslashes. Returns the index following the path, leaving
+// room for a NUL terminator.
+fn encode_path(buffer: &mut [u16; PATH_MAX], start: usize, path: &str) -> Option<usize> {
+    let mut n = start;
+    for c in path.chars() {
+        let c = if c == '/' { '\\' } else { c };
+        let mut units = [0u16; 2];
+        for unit in c.encode_utf16(&mut units) {
+            if n + 1 >= PATH_MAX {
+                return None;
+            }
+            buffer[n] = *unit;
+            n += 1;
+        }
+    }
+    Some(n)
+}
+
+// Get the size of a file in bytes.
+pub fn file_size(file: *mut file::Protocol) -> usize {
+    let info_buffer = allocate_pool(256).expect("failed to allocate file info buffer");
+    let mut finfo_guid = file::INFO_ID;
+    let mut size = 256;
+    let _ = unsafe {
+        ((*file).get_info)(
+            file,
+            &mut finfo_guid,
+            &mut size,
+            info_buffer as *mut core::ffi::c_void,
+        )
+    };
+    let file_len = unsafe { (*(info_buffer as *const file::Info)).file_size as usize };
+    free_pool(info_buffer);
+
+    file_len
+}
+
+// Read a whole file into newly allocated pages, return its start address and length.
+// The contents can be freed with free_file().
+pub fn read_file(file: *mut file::Protocol) -> Option<(usize, usize)> {
+    let size = file_size(file);
+    let n = file_pages(size);
+    let start = allocate_pages(n)?;
+    let _ = unsafe { ((*file).set_position)(file, 0) };
+    // A short read would leave the length out of step with the pages, so
+    // free_file() could not free them.
+    let mut file_len = size;
+    let status = unsafe { ((*file).read)(file, &mut file_len, start as *mut core::ffi::c_void) };
+    if status.is_error() || file_len != size {
+        if status.is_error() {
+            println!("ERROR: read_file {:?}", status);
+        } else {
+            println!("ERROR: read_file: short read");
+        }
+        free_pages(start, n);
+        None
+    } else {
+        Some((start, file_len))
+    }
+}
+
+// Free the contents of a file read with read_file().
+pub fn free_file(start: usize, length: usize) {
+    free_pages(start, file_pages(length));
+}
+
+// Get the number of pages read_file() allocates for a file, at least one so
+// that an empty file still has an address.
+fn file_pages(length: usize) -> usize {
+    length.div_ceil(arch::PAGE_SIZE).max(1)
+}
@@ -3,9 +3,8 @@
 use crate::ST;
 use r_efi::efi::protocols::graphics_output;
 
-// Get a pointer to information about the current graphics mode.
-pub fn get_mode() -> Option<usize> {
-    // Obtain a pointer to graphics output protocol on the first device handle supporting it.
+// Obtain a pointer to graphics output protocol on the first device handle supporting it.
+fn locate_gop() -> Option<*mut graphics_output::Protocol> {
     let mut guid = graphics_output::PROTOCOL_GUID;
     let mut gop = 0 as *mut graphics_output::Protocol;
     let status = unsafe {
@@ -20,6 +19,94 @@ pub fn get_mode() -> Option<usize> {
         return None;
     }
 
+    Some(gop)
+}
+
+// Set the graphics mode with the given resolution, if the device supports one.
+pub fn set_resolution(h_res: u32, v_res: u32) {
+    let gop = match locate_gop() {
+        Some(gop) => gop,
+        None => return,
+    };
+
+    // Check all the video modes for a matching one.
+    let max_mode = unsafe { (*(*gop).mode).max_mode };
+    for x in 0..max_mode {
+        let mut size = 0usize;
+        let mut info = 0 as *mut graphics_output::ModeInformation;
+        let status = unsafe { ((*gop).query_mode)(gop, x, &mut size, &mut info) };
+        if status.is_error() {
+            println!("NOTE: query mode for mode {} failed", x);
+            continue;
+        }
+
+        let (mh_res, mv_res) = unsafe { ((*info).horizontal_resolution, (*info).vertical_resolution) };
+        if mh_res == h_res && mv_res == v_res {
+            let status = unsafe { ((*gop).set_mode)(gop, x) };
+            if status.is_error() {
+                println!("WARNING: failed to set graphics mode {}: {:?}", x, status);
+            }
+            return;
+        }
+    }
+
+    println!("WARNING: no graphics mode with resolution {}p by {}p", h_res, v_res);
+}
+
+// Make sure the graphics mode has at least the given resolution, switching to
+// the smallest mode that does if the current one is too small. Return whether
+// the resolution is met.
+pub fn set_min_resolution(h_res: u32, v_res: u32) -> bool {
+    let gop = match locate_gop() {
+        Some(gop) => gop,
+        None => return false,
+    };
+
+    let ch_res = unsafe { (*(*(*gop).mode).info).horizontal_resolution };
+    let cv_res = unsafe { (*(*(*gop).mode).info).vertical_resolution };
+    if ch_res >= h_res && cv_res >= v_res {
+        return true;
+    }
+
+    // Find the mode with the fewest pixels among those that are large enough.
+    let mut best: Option<(u32, u64)> = None;
+    let max_mode = unsafe { (*(*gop).mode).max_mode };
+    for x in 0..max_mode {
+        let mut size = 0usize;
+        let mut info = 0 as *mut graphics_output::ModeInformation;
+        let status = unsafe { ((*gop).query_mode)(gop, x, &mut size, &mut info) };
+        if status.is_error() {
+            println!("NOTE: query mode for mode {} failed", x);
+            continue;
+        }
+
+        let (mh_res, mv_res) = unsafe { ((*info).horizontal_resolution, (*info).vertical_resolution) };
+        let pixels = mh_res as u64 * mv_res as u64;
+        if mh_res >= h_res && mv_res >= v_res && best.is_none_or(|(_, p)| pixels < p) {
+            best = Some((x, pixels));
+        }
+    }
+
+    let mode = match best {
+        Some((mode, _)) => mode,
+        None => {
+            println!("WARNING: no graphics mode of at least {}p by {}p", h_res, v_res);
+            return false;
+        }
+    };
+    let status = unsafe { ((*gop).set_mode)(gop, mode) };
+    if status.is_error() {
+        println!("WARNING: failed to set graphics mode {}: {:?}", mode, status);
+        return false;
+    }
+
+    true
+}
+
+// Get a pointer to information about the current graphics mode.
+pub fn get_mode() -> Option<usize> {
+    let gop = locate_gop()?;
+
     // Print information about the active graphics mode.
     let ch_res = unsafe { (*(*(*gop).mode).info).horizontal_resolution };
     let cv_res = unsafe { (*(*(*gop).mode).info).vertical_resolution };
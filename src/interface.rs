@@ -3,6 +3,27 @@
 /// The magic number.
 pub const MAGIC: u64 = 0xfedcba9876543210;
 
+/// The owner name of the ELF notes holding a kernel's boot requests.
+///
+/// Notes are read from the kernel's note segments, or from its
+/// `.note.uefi-boot` section if it has no note segment.
+pub const NOTE_OWNER: &str = "uefi-boot";
+/// Note type requesting a minimum framebuffer resolution. The descriptor holds
+/// the horizontal and vertical resolution as two little-endian `u32`.
+pub const NOTE_FRAMEBUFFER: u32 = 1;
+/// Note type requesting a stack of at least the given size, mapped below the
+/// kernel image with an unmapped guard page between them. The descriptor holds
+/// the size in bytes as a little-endian `u64`.
+pub const NOTE_STACK_SIZE: u32 = 2;
+/// Note type requesting a direct map of physical memory at an offset in the
+/// higher half. The descriptor holds the offset as a little-endian `u64`. This
+/// request is not supported yet.
+pub const NOTE_DIRECT_MAP: u32 = 3;
+/// Note type stating whether the identity map of physical memory must be kept.
+/// The descriptor holds a little-endian `u32`, nonzero to keep the identity
+/// map. The identity map is always kept, so only nonzero values are supported.
+pub const NOTE_IDENTITY_MAP: u32 = 4;
+
 /// Boot information data structure.
 /// 
 /// This structure provides information necessary for the kernel to take 
@@ -11,21 +32,93 @@ pub const MAGIC: u64 = 0xfedcba9876543210;
 /// All provided pointers are strictly physical addresses. If the kernel unmaps
 /// the system identity mapping of all physical memory, it must adjust those 
 /// pointers accordingly, or not use them at all.
+///
+/// The structure has a C layout with 64-bit fields, so that 32-bit kernels can
+/// read it as well. Pointers that are absent are zero. For 32-bit kernels, all
+/// pointers are below 4 GiB, except the EFI system table and graphics mode,
+/// which point to 64-bit firmware structures anyway.
+#[repr(C)]
 pub struct BootInfo {
     /// Pointer to the EFI memory map.
-    pub efi_mmap_start: usize,
+    pub efi_mmap_start: u64,
     /// Length of the EFI memory map.
-    pub efi_mmap_length: usize,
+    pub efi_mmap_length: u64,
     /// The size of each EFI descriptor entry.
-    pub efi_mmap_desc_size: usize,
+    pub efi_mmap_desc_size: u64,
+
+    /// The offset added to the kernel's link addresses when it was loaded.
+    /// It is zero unless the kernel is position-independent, and random if its
+    /// load address was randomized. Debuggers add it to symbol addresses.
+    pub kernel_slide: u64,
+
+    /// A copy of the kernel's ELF section header table, or zero if the kernel
+    /// has none. The symbol table, its string table and the section name
+    /// string table are copied into memory as well, and the `sh_addr` field of
+    /// their headers holds the physical address of the copy. Other sections
+    /// keep their link address.
+    pub elf_sections_start: u64,
+    /// The number of entries in the section header table.
+    pub elf_sections_count: u64,
+    /// The size of each section header table entry.
+    pub elf_section_size: u64,
+    /// The index of the section name string table.
+    pub elf_section_names_index: u64,
+
+    /// The start of the ramdisk in memory, or zero if no ramdisk was loaded.
+    pub ramdisk_start: u64,
+    /// The length of the ramdisk in bytes, zero if no ramdisk was loaded.
+    pub ramdisk_length: u64,
+
+    /// The start of the NUL-terminated UTF-8 kernel command line.
+    pub cmdline_start: u64,
+    /// The length of the command line in bytes, excluding the NUL terminator.
+    pub cmdline_length: u64,
 
-    /// The start of the ramdisk in memory.
-    pub ramdisk_start: usize,
-    /// The length of the ramdisk in bytes.
-    pub ramdisk_length: usize,
+    /// Pointer to an array of `Module` structures.
+    pub modules_start: u64,
+    /// The number of entries in the module array.
+    pub modules_count: u64,
 
     /// A pointer to the EFI system table.
-    pub efi_system_table: usize,
-    /// A pointer to the active graphics output protocol mode structure.
-    pub efi_gop_modes: Option<usize>,
+    pub efi_system_table: u64,
+    /// A pointer to the active graphics output protocol mode structure, or zero
+    /// if there is no graphics output.
+    pub efi_gop_modes: u64,
+
+    /// The virtual address of the kernel's thread-local storage template, or
+    /// zero if the kernel has no TLS segment.
+    pub tls_template_start: u64,
+    /// The size of the initialized part of the TLS template (`.tdata`).
+    pub tls_template_file_size: u64,
+    /// The size of a TLS block, including the zeroed part (`.tbss`).
+    pub tls_template_mem_size: u64,
+    /// The alignment of a TLS block.
+    pub tls_template_align: u64,
+    /// The thread pointer of a TLS block the loader set up for the boot
+    /// processor, or zero if the kernel has no TLS segment. The FS segment base
+    /// is set to it before entering the kernel. The block lives in physical memory.
+    pub tls_thread_pointer: u64,
+
+    /// The boot requests in the kernel's notes that were not honored, as a bit
+    /// mask with bit `n` set for note type `n`.
+    pub unsupported_requests: u64,
+
+    /// The x86 control-flow enforcement features the kernel is marked as
+    /// compatible with in its GNU property note: bit 0 for indirect branch
+    /// tracking (IBT) and bit 1 for shadow stacks (SHSTK). Both are disabled
+    /// when the kernel is entered, and the kernel may enable those it supports.
+    pub x86_features: u64,
+}
+
+/// A boot module loaded into memory alongside the kernel.
+#[repr(C)]
+pub struct Module {
+    /// The start of the module in memory.
+    pub start: u64,
+    /// The length of the module in bytes.
+    pub length: u64,
+    /// The start of the NUL-terminated UTF-8 string given with the module.
+    pub cmdline_start: u64,
+    /// The length of the string in bytes, excluding the NUL terminator.
+    pub cmdline_length: u64,
 }
\ No newline at end of file
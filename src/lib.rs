@@ -9,6 +9,17 @@
 //! NOTE: "sysv64" applies to x86_64 systems; this is the only supported 
 //! architecture now
 //! 
+//! 32-bit kernels (ELFCLASS32) are loaded at the physical addresses in their
+//! program headers and entered in protected mode, with paging off and flat
+//! code and data segments. Their entry function uses the cdecl convention:
+//! ```rust
+//! extern "cdecl" fn(magic: u64, info_addr: u32);
+//! ```
+//! The low half of the magic number is also passed in `eax` and the address of
+//! the boot information structure in `ebx`. The structure has a C layout with
+//! 64-bit fields, and everything it points to is below 4 GiB, except the EFI
+//! system table and graphics mode.
+//! 
 //! The entry function itself should validate the magic number before accessing
 //! the boot information structure, in order to verify that it was called by
 //! uefi-boot.
@@ -25,4 +36,10 @@
 mod interface;
 
 pub use self::interface::MAGIC as MAGIC;
-pub use self::interface::BootInfo as BootInfo;
\ No newline at end of file
+pub use self::interface::NOTE_OWNER as NOTE_OWNER;
+pub use self::interface::NOTE_FRAMEBUFFER as NOTE_FRAMEBUFFER;
+pub use self::interface::NOTE_STACK_SIZE as NOTE_STACK_SIZE;
+pub use self::interface::NOTE_DIRECT_MAP as NOTE_DIRECT_MAP;
+pub use self::interface::NOTE_IDENTITY_MAP as NOTE_IDENTITY_MAP;
+pub use self::interface::BootInfo as BootInfo;
+pub use self::interface::Module as Module;
\ No newline at end of file
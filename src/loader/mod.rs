@@ -1,159 +1,629 @@
 // Loaders for kernels and ramdisks
 
-mod elf64;
+mod physical;
+mod protected;
+mod reloc;
+mod requests;
+mod sections;
+mod validate;
 
-use crate::{arch, env, ST};
-use elf64::{program::PHType, Elf64, ElfAbi, ElfType};
+use crate::interface::NOTE_STACK_SIZE;
+use crate::{arch, env, rng};
+use core::fmt;
+use elf64::elf32::{Elf32, Elf32Error};
+use elf64::{program::PHType, Elf64, Elf64Error, ElfAbi, ElfType, ProgramHeader};
+use physical::PhysicalImage;
 use r_efi::efi::protocols::file;
+use requests::Requests;
+use sections::Sections;
 
-// Load the kernel into memory from a file, return the entry point.
-pub fn load_kernel(kfile: *mut file::Protocol) -> usize {
-    // Get the length of the kernel file.
-    let info_buffer = env::allocate_pool(256).expect("failed to allocate file info buffer");
-    let mut finfo_guid = file::INFO_ID;
-    let mut size = 256; // whole page available for buffer
-    let _ = unsafe {
-        ((*kfile).get_info)(
-            kfile,
-            &mut finfo_guid,
-            &mut size,
-            info_buffer as *mut core::ffi::c_void,
-        )
-    };
-    let kfile_len = unsafe { (*(info_buffer as *const file::Info)).file_size as usize };
-    env::free_pool(info_buffer);
+// Reasons that loading a boot entry can fail.
+#[derive(Debug)]
+pub enum LoadError {
+    // A file could not be opened (holds the path).
+    NotFound(&'static str),
+    // The contents of a file could not be read.
+    Read,
+    // Memory for the kernel image could not be allocated.
+    OutOfMemory,
+    // The kernel file is not an ELF-64 file.
+    NotElf64(Elf64Error),
+    // The kernel file is an ELF-32 file, but a malformed one.
+    NotElf32(Elf32Error),
+    // The kernel ELF is not for this machine.
+    WrongMachine,
+    // The kernel ELF requires ABI extensions or a nonzero ABI version.
+    UnsupportedAbi,
+    // The kernel ELF is neither an executable nor position-independent.
+    NotExecutable,
+    // A kernel segment's address and file offset are at different positions
+    // in their pages (holds the segment's virtual address).
+    MisalignedSegment(u64),
+    // The kernel ELF is corrupt.
+    Corrupt,
+    // The dynamic array of a position-independent kernel is malformed.
+    BadDynamic(Elf64Error),
+    // A relocation has an unsupported type.
+    UnsupportedRelocation(u32),
+    // A relocation refers to an undefined symbol (holds the symbol index).
+    UndefinedSymbol(u32),
+    // A relocation targets an address outside the kernel image.
+    RelocationOutOfRange(u64),
+    // The kernel has no loadable segments.
+    NoSegments,
+    // A kernel segment is not in the higher half (holds its virtual address).
+    LowerHalfSegment(u64),
+    // A segment of an identity-mapped kernel is not linked at its physical
+    // address in the lower half (holds its virtual address).
+    NotIdentityMapped(u64),
+    // A kernel segment overlaps the previous one or is out of order (holds its
+    // virtual address).
+    OverlappingSegments(u64),
+    // A segment of a 32-bit kernel ends above 4 GiB, out of its reach (holds
+    // its physical address).
+    SegmentAbove4GiB(u64),
+    // The kernel's entry point is not in an executable segment.
+    BadEntry(u64),
+    // The kernel's TLS segment has an alignment that is not a power of two or
+    // is larger than a page (holds the alignment).
+    BadTlsAlignment(u64),
+    // The notes holding the kernel's boot requests are malformed.
+    BadNotes(Elf64Error),
+    // Memory that a kernel must be loaded at is in use (holds the address).
+    AddressInUse(u64),
+}
 
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotFound(path) => write!(f, "failed to open {}", path),
+            LoadError::Read => write!(f, "failed to read file contents"),
+            LoadError::OutOfMemory => write!(f, "failed to allocate pages to load kernel image"),
+            LoadError::NotElf64(e) => write!(f, "unable to parse kernel file as ELF-64: {:?}", e),
+            LoadError::NotElf32(e) => write!(f, "unable to parse kernel file as ELF-32: {:?}", e),
+            LoadError::WrongMachine => write!(f, "the kernel ELF is not for this machine"),
+            LoadError::UnsupportedAbi => write!(f, "the kernel ELF requires ABI extensions to load"),
+            LoadError::NotExecutable => {
+                write!(f, "the kernel ELF is neither executable nor position-independent")
+            }
+            LoadError::MisalignedSegment(vaddr) => {
+                write!(f, "ELF segment at {:#x} is not page aligned like its file offset", vaddr)
+            }
+            LoadError::Corrupt => write!(f, "the kernel ELF is corrupt"),
+            LoadError::BadDynamic(e) => write!(f, "the kernel's dynamic array is malformed: {:?}", e),
+            LoadError::UnsupportedRelocation(t) => write!(f, "unsupported relocation type {}", t),
+            LoadError::UndefinedSymbol(x) => write!(f, "relocation against undefined symbol {}", x),
+            LoadError::RelocationOutOfRange(addr) => {
+                write!(f, "relocation at {:#x} is outside the kernel image", addr)
+            }
+            LoadError::NoSegments => write!(f, "the kernel has no loadable segments"),
+            LoadError::LowerHalfSegment(addr) => {
+                write!(f, "kernel segment at {:#x} is not in the higher half", addr)
+            }
+            LoadError::NotIdentityMapped(addr) => {
+                write!(f, "kernel segment at {:#x} is not at its physical address in the lower half", addr)
+            }
+            LoadError::OverlappingSegments(addr) => {
+                write!(f, "kernel segment at {:#x} overlaps the previous one or is out of order", addr)
+            }
+            LoadError::SegmentAbove4GiB(addr) => {
+                write!(f, "32-bit kernel segment at {:#x} does not fit below 4 GiB", addr)
+            }
+            LoadError::BadEntry(addr) => {
+                write!(f, "the kernel's entry point {:#x} is not in an executable segment", addr)
+            }
+            LoadError::BadTlsAlignment(align) => {
+                write!(f, "the kernel's TLS alignment {:#x} is not supported", align)
+            }
+            LoadError::BadNotes(e) => write!(f, "the kernel's boot request notes are malformed: {:?}", e),
+            LoadError::AddressInUse(addr) => {
+                write!(f, "memory at {:#x} needed by the kernel is in use", addr)
+            }
+        }
+    }
+}
+
+// The load base of position-independent kernels when none is configured.
+pub const DEFAULT_BASE: u64 = 0xffffffff80000000;
+
+// The range of virtual addresses that randomized kernels are loaded in: the
+// last 512 GiB of the address space, except for the top 2 MiB.
+const KASLR_START: u64 = 0xffffff8000000000;
+const KASLR_END: u64 = 0xffffffffffe00000;
+
+// The minimum alignment of a randomized load base.
+const KASLR_ALIGN: u64 = 0x200000;
+
+// How the load address of the kernel is randomized.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kaslr {
+    // The kernel is loaded at fixed addresses.
+    Off,
+    // Position-independent kernels are loaded at a random virtual base.
+    Virtual,
+    // The kernel is also placed at a random physical address.
+    Full,
+}
+
+// A kernel loaded into memory.
+pub struct Kernel {
+    // The address of the kernel's entry point.
+    pub entry: usize,
+    // The offset added to the kernel's link addresses, zero unless the kernel
+    // is position-independent.
+    pub slide: u64,
+    // A copy of the kernel's section headers, if it has any.
+    pub sections: Option<Sections>,
+    // The boot requests of the kernel.
+    pub requests: Requests,
+    // The top of the stack requested by the kernel, if it could be mapped.
+    pub stack_top: Option<usize>,
+    // The kernel's thread-local storage, if it has any.
+    pub tls: Option<Tls>,
+    // The trampoline entering the kernel in protected mode, if it is 32-bit.
+    pub trampoline: Option<usize>,
+    // The x86 control-flow enforcement features the kernel is compatible with.
+    pub x86_features: u32,
+    // The pages the kernel was loaded at physical addresses, which must be
+    // freed if the rest of its boot entry fails to load.
+    pub image: PhysicalImage,
+}
+
+// The thread-local storage of a kernel.
+pub struct Tls {
+    // The virtual address of the TLS template.
+    pub start: usize,
+    // The size of the initialized part of the template.
+    pub file_size: usize,
+    // The size of a TLS block.
+    pub mem_size: usize,
+    // The alignment of a TLS block.
+    pub align: usize,
+    // The thread pointer of the TLS block set up for the boot processor.
+    pub thread_pointer: usize,
+}
+
+// Load the kernel into memory from a file.
+// Position-independent kernels are loaded at the given base address, or at a
+// random one if virtual address randomization is enabled.
+//
+// Segments are copied into frames of their own, so the kernel file is freed
+// once the kernel is loaded. On failure, any mappings that were made are also
+// removed and any pages allocated at fixed addresses are freed, so another
+// kernel can be loaded in its place. Once the kernel is loaded, the caller
+// frees the pages in `Kernel::image` if the rest of the boot entry fails.
+pub fn load_kernel(kfile: *mut file::Protocol, base: u64, kaslr: Kaslr) -> Result<Kernel, LoadError> {
     // Load the kernel file contents into memory.
-    assert_ne!(kfile_len, 0, "kernel file length must not be zero");
-    let n = kfile_len / arch::PAGE_SIZE + 1;
-    let kfile_start_page = env::allocate_pages(n).expect("failed to allocate kernel file pages");
-    let _ = unsafe { ((*kfile).set_position)(kfile, 0) };
-    let status = unsafe {
-        ((*kfile).read)(
-            kfile,
-            &mut (kfile_len as usize),
-            kfile_start_page as *mut core::ffi::c_void,
-        )
+    let (kfile_start_page, kfile_len) = env::read_file(kfile).ok_or(LoadError::Read)?;
+
+    // 32-bit kernels are loaded at their physical addresses instead of being mapped.
+    let slice = unsafe { core::slice::from_raw_parts(kfile_start_page as *const u8, kfile_len) };
+    let result = if Elf32::is_elf32(slice) {
+        protected::load_kernel32(slice)
+    } else {
+        map_kernel(kfile_start_page, kfile_len, base, kaslr)
     };
-    if status.is_error() {
-        panic!("failed to read contents of kernel file");
+    if result.is_err() {
+        // The frames and page tables of partial mappings are leaked, which is harmless.
+        arch::prepare_root_pt();
     }
+    env::free_file(kfile_start_page, kfile_len);
+
+    result
+}
 
+// Load the segments of an ELF-64 kernel file, mapped in the higher half or
+// identity-mapped at their physical addresses.
+fn map_kernel(
+    kfile_start_page: usize,
+    kfile_len: usize,
+    base: u64,
+    kaslr: Kaslr,
+) -> Result<Kernel, LoadError> {
     // Try to read the kernel file as an ELF-64 executable.
     let slice = unsafe { core::slice::from_raw_parts(kfile_start_page as *const u8, kfile_len) };
-    let elf = Elf64::from_slice(slice).expect("unable to parse kernel file as ELF-64");
+    let elf = Elf64::from_slice(slice).map_err(LoadError::NotElf64)?;
 
     // Check some ELF header fields to see if efiloader can load it.
-    assert!(
-        elf.is_valid_locally(),
-        "the kernel ELF is not for this machine"
-    );
-    assert_eq!(
-        elf.abi(),
-        ElfAbi::None,
-        "the kernel ELF requires ABI extensions to load"
-    );
-    assert_eq!(elf.abi_version(), 0, "the kernel ELF ABI version is not 0");
-    assert_eq!(
-        elf.file_type(),
-        ElfType::Executable,
-        "the kernel ELF is not executable"
-    );
-
-    for segment in elf.program_headers().expect("the kernel ELF is corrupt") {
+    if !elf.is_valid_locally() {
+        return Err(LoadError::WrongMachine);
+    }
+    if elf.abi() != ElfAbi::None || elf.abi_version() != 0 {
+        return Err(LoadError::UnsupportedAbi);
+    }
+
+    // Executables run at their link address, position-independent kernels at the base.
+    let base = match elf.file_type() {
+        ElfType::Executable => 0,
+        ElfType::SharedObject if kaslr != Kaslr::Off => random_base(&elf)?,
+        ElfType::SharedObject => base,
+        _ => return Err(LoadError::NotExecutable),
+    };
+    let identity = validate::is_identity_mapped(&elf)?;
+    if kaslr != Kaslr::Off && identity {
+        println!("WARNING: kernel is identity-mapped, its addresses are fixed");
+    } else if kaslr != Kaslr::Off && elf.file_type() == ElfType::Executable {
+        println!("WARNING: kernel is not position-independent, its virtual address is fixed");
+    }
+
+    // Reject bad layouts before any page is mapped or allocated.
+    validate::validate_kernel(&elf, base, identity)?;
+
+    // Identity-mapped kernels are loaded at their physical addresses, whose
+    // pages are freed if loading fails so that another kernel can use them.
+    let mut image = PhysicalImage::new();
+    let result = if identity {
+        load_identity(&elf, &mut image)
+    } else {
+        map_segments(&elf, kfile_start_page, base, kaslr)
+    }
+    .and_then(|image_start| prepare_kernel(&elf, base, identity, image_start, &mut image));
+    if result.is_err() {
+        image.free();
+    }
+
+    result
+}
+
+// Map the loadable segments of a kernel at a base address in the higher half,
+// return the lowest address of the image.
+fn map_segments(
+    elf: &Elf64,
+    kfile_start_page: usize,
+    base: u64,
+    kaslr: Kaslr,
+) -> Result<usize, LoadError> {
+    // The lowest address of the kernel image.
+    let mut image_start = usize::MAX;
+
+    for segment in elf.program_headers().map_err(LoadError::NotElf64)? {
         // Map only loadable segments.
         if segment.type_() == PHType::Load {
-            assert!(
-                arch::check_page_alignment(segment.offset as usize),
-                "ELF segments must be 4k aligned"
-            );
-            assert!(
-                arch::check_page_alignment(segment.vaddr as usize),
-                "ELF segments must be 4k aligned"
-            );
-            assert!(elf.contains(segment), "the kernel ELF is corrupt");
-
-            // Calculate how many pages come from the file vs. must be allocated.
-            let total_pages = segment.memsz as usize / arch::PAGE_SIZE + 1;
-            let n_pages_from_file = segment.filesz as usize / arch::PAGE_SIZE + 1;
-            let n_alloc_pages = total_pages - n_pages_from_file;
-
-            // Calculate the segment's start page in memory.
-            let seg_start_page = kfile_start_page + segment.offset as usize;
-
-            // Map pages from the ELF.
-            for x in 0..n_pages_from_file {
-                let p_offset = x * arch::PAGE_SIZE;
-                arch::map(
-                    seg_start_page + p_offset as usize,
-                    segment.vaddr as usize + p_offset,
-                );
-            }
-
-            if n_alloc_pages != 0 {
-                // Allocate additional pages for the segment.
-                let alloc_start_page = env::allocate_pages(n_alloc_pages)
-                    .expect("failed to allocate pages to load kernel image");
-
-                // Map remaining pages from allocated pages.
-                for x in n_pages_from_file..total_pages {
-                    let p_offset = (x - n_pages_from_file) * arch::PAGE_SIZE;
-                    let m_offset = x * arch::PAGE_SIZE;
-                    arch::map(
-                        alloc_start_page + p_offset as usize,
-                        segment.vaddr as usize + m_offset,
-                    );
-                }
+            // The layout of the segments was validated, and the parser checked
+            // that their contents are in the file and fit in memory.
+            let vaddr = segment.vaddr.wrapping_add(base);
+            if segment.memsz == 0 {
+                continue;
             }
 
-            // Zero the memory between filesz and memsz.
-            let zeroed_start = segment.vaddr + segment.filesz;
-            let zeroed_len = segment.memsz - segment.filesz;
-            let _ = unsafe {
-                ((*(*ST).boot_services).set_mem)(
-                    zeroed_start as *mut core::ffi::c_void,
-                    zeroed_len as usize,
-                    0,
-                )
+            // Pages get the segment's permissions. Segments that are both
+            // writable and executable break W^X for the whole kernel image.
+            let permissions = segment.permissions();
+            let writable = permissions.is_writable();
+            let executable = permissions.is_executable();
+            if writable && executable {
+                println!("WARNING: kernel segment at {:#x} is writable and executable", vaddr);
+            }
+
+            // Calculate the bounds of the segment and of its contents in the file.
+            let start = vaddr as usize;
+            let end = start.checked_add(segment.memsz as usize).ok_or(LoadError::Corrupt)?;
+            let file_start = kfile_start_page + segment.offset as usize;
+            let first_page = start & !(arch::PAGE_SIZE - 1);
+            let n_pages = (end - first_page).div_ceil(arch::PAGE_SIZE);
+            image_start = image_start.min(first_page);
+
+            // A first page shared with the previous segment keeps its frame. The
+            // other pages get zeroed frames of their own, randomly placed with
+            // full randomization.
+            let shared = arch::translate(first_page).is_some();
+            let n_alloc_pages = n_pages - shared as usize;
+            let frames = if n_alloc_pages == 0 {
+                0
+            } else {
+                let frames = match kaslr {
+                    Kaslr::Full => env::allocate_pages_random(n_alloc_pages, rng::random_u64()),
+                    _ => env::allocate_pages(n_alloc_pages),
+                }
+                .ok_or(LoadError::OutOfMemory)?;
+                unsafe {
+                    core::ptr::write_bytes(frames as *mut u8, 0, n_alloc_pages * arch::PAGE_SIZE)
+                };
+                frames
             };
+
+            for x in 0..n_pages {
+                let page = first_page + x * arch::PAGE_SIZE;
+                if x == 0 && shared {
+                    share_page(page, writable, executable);
+                } else if arch::translate(page).is_some() {
+                    // Segments must be sorted and only overlap in a shared page.
+                    return Err(LoadError::Corrupt);
+                } else {
+                    let frame = frames + (x - shared as usize) * arch::PAGE_SIZE;
+                    arch::map(frame, page, writable, executable);
+                }
+            }
+
+            // Copy the file contents of the segment page by page, since the
+            // frame of a shared first page is not contiguous with the others.
+            // The rest of the segment is zero from allocating the frames.
+            let mut copied = 0;
+            while copied < segment.filesz as usize {
+                let frame = arch::translate(start + copied).ok_or(LoadError::Corrupt)?;
+                let n = (arch::PAGE_SIZE - frame % arch::PAGE_SIZE)
+                    .min(segment.filesz as usize - copied);
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        (file_start + copied) as *const u8,
+                        frame as *mut u8,
+                        n,
+                    )
+                };
+                copied += n;
+            }
         }
     }
 
-    elf.entry() as usize
+    Ok(image_start)
 }
 
-// Load a ramdisk into memory from a file, return its start address and length.
-pub fn load_ramdisk(rdfile: *mut file::Protocol) -> (usize, usize) {
-    // Get the length of the ramdisk file.
-    let info_buffer = env::allocate_pool(256).expect("failed to allocate file info buffer");
-    let mut finfo_guid = file::INFO_ID;
-    let mut size = 256; // whole page available for buffer
-    let _ = unsafe {
-        ((*rdfile).get_info)(
-            rdfile,
-            &mut finfo_guid,
-            &mut size,
-            info_buffer as *mut core::ffi::c_void,
-        )
+// Load the loadable segments of an identity-mapped kernel at their physical
+// addresses, which the firmware maps to the same virtual addresses. The pages
+// keep the firmware's permissions. Return the lowest address of the image.
+fn load_identity(elf: &Elf64, image: &mut PhysicalImage) -> Result<usize, LoadError> {
+    let mut image_start = usize::MAX;
+    for segment in elf.program_headers().map_err(LoadError::NotElf64)? {
+        if segment.type_() == PHType::Load && segment.memsz != 0 {
+            let data = elf.segment_data(segment).map_err(LoadError::NotElf64)?;
+            let executable = segment.permissions().is_executable();
+            image.load_segment(segment.paddr, segment.memsz, data, executable)?;
+            image_start = image_start.min(segment.paddr as usize & !(arch::PAGE_SIZE - 1));
+        }
+    }
+
+    Ok(image_start)
+}
+
+// Finish loading a kernel whose segments are in memory: relocate it, then
+// gather what it is passed at entry. The pages it was loaded at physical
+// addresses move to the returned kernel.
+fn prepare_kernel(
+    elf: &Elf64,
+    base: u64,
+    identity: bool,
+    image_start: usize,
+    image: &mut PhysicalImage,
+) -> Result<Kernel, LoadError> {
+    // Position-independent kernels must be relocated to run at the base. Then
+    // the RELRO range is no longer written to, except in identity-mapped
+    // kernels, which keep the firmware's permissions.
+    if elf.file_type() == ElfType::SharedObject {
+        reloc::relocate(elf, base)?;
+    }
+    if !identity {
+        protect_relro(elf, base)?;
+    }
+
+    // The kernel's symbols are optional, so malformed sections don't fail the boot.
+    let sections = match sections::copy_sections(elf) {
+        Ok(sections) => sections,
+        Err(e) => {
+            println!("WARNING: not passing symbols, the kernel's sections are malformed: {:?}", e);
+            None
+        }
+    };
+
+    // Requests that the loader honors here are a stack below the image, which
+    // is executable only if the kernel's GNU_STACK segment says so. Identity-
+    // mapped kernels get their stack anywhere in memory, without a guard page.
+    let mut requests = requests::parse_requests(elf).map_err(LoadError::BadNotes)?;
+    let executable_stack = elf
+        .program_headers()
+        .map_err(LoadError::NotElf64)?
+        .find(|s| s.type_() == PHType::GnuStack)
+        .is_some_and(|s| s.permissions().is_executable());
+    let stack_top = match requests.stack_size {
+        Some(size) => {
+            if executable_stack {
+                println!("WARNING: the kernel requests an executable stack");
+            }
+            let stack_top = if identity {
+                allocate_stack(size)
+            } else {
+                map_stack(image_start, size, executable_stack)
+            };
+            if stack_top.is_none() {
+                println!("WARNING: failed to map a kernel stack of {} bytes", size);
+                requests.unsupported(NOTE_STACK_SIZE);
+            }
+            stack_top
+        }
+        None => None,
+    };
+
+    // Kernels with thread-local storage get a TLS block for the boot processor.
+    let tls_segment = elf
+        .program_headers()
+        .map_err(LoadError::NotElf64)?
+        .find(|s| s.type_() == PHType::ThreadLocalStorage);
+    let tls = match tls_segment {
+        Some(segment) => Some(setup_tls(segment, base, identity)?),
+        None => None,
+    };
+
+    Ok(Kernel {
+        entry: elf.entry().wrapping_add(base) as usize,
+        slide: base,
+        sections,
+        requests,
+        stack_top,
+        tls,
+        trampoline: None,
+        x86_features: elf.x86_features().map_err(LoadError::NotElf64)?,
+        image: core::mem::replace(image, PhysicalImage::new()),
+    })
+}
+
+// Set up a TLS block for the boot processor from a kernel's TLS segment, using
+// the x86_64 layout: the block ends at the thread pointer, where the thread
+// control block starts with a pointer to itself.
+fn setup_tls(segment: &ProgramHeader, base: u64, identity: bool) -> Result<Tls, LoadError> {
+    let align = match segment.align {
+        0 => 1,
+        align if align.is_power_of_two() => align as usize,
+        align => return Err(LoadError::BadTlsAlignment(align)),
     };
-    let rdfile_len = unsafe { (*(info_buffer as *const file::Info)).file_size as usize };
-    env::free_pool(info_buffer);
-
-    // Load the ramdisk file contents into memory.
-    assert_ne!(rdfile_len, 0, "ramdisk file length must not be zero");
-    let n = rdfile_len / arch::PAGE_SIZE + 1;
-    let rdfile_start_page = env::allocate_pages(n).expect("failed to allocate ramdisk file pages");
-    let _ = unsafe { ((*rdfile).set_position)(rdfile, 0) };
-    let status = unsafe {
-        ((*rdfile).read)(
-            rdfile,
-            &mut (rdfile_len as usize),
-            rdfile_start_page as *mut core::ffi::c_void,
-        )
+    let file_size = segment.filesz as usize;
+    let mem_size = segment.memsz as usize;
+
+    // Leave room to align the thread pointer, then for the thread control block.
+    let offset = mem_size.checked_add(align - 1).ok_or(LoadError::Corrupt)? & !(align - 1);
+    let size = offset
+        .checked_add(align)
+        .and_then(|size| size.checked_add(core::mem::size_of::<usize>()))
+        .ok_or(LoadError::Corrupt)?;
+    let n_pages = size.div_ceil(arch::PAGE_SIZE);
+    let frames = env::allocate_pages(n_pages).ok_or(LoadError::OutOfMemory)?;
+    let thread_pointer = match frames.checked_add(offset).and_then(|x| x.checked_add(align - 1)) {
+        Some(end) => end & !(align - 1),
+        None => {
+            env::free_pages(frames, n_pages);
+            return Err(LoadError::Corrupt);
+        }
     };
-    if status.is_error() {
-        panic!("failed to read contents of ramdisk file");
+    let block = thread_pointer - offset;
+
+    // Copy the template from the loaded image, where it is already relocated,
+    // then zero the rest of the block. The image is read through the physical
+    // addresses of its pages, unless it is identity-mapped.
+    let start = segment.vaddr.wrapping_add(base) as usize;
+    unsafe { core::ptr::write_bytes(frames as *mut u8, 0, n_pages * arch::PAGE_SIZE) };
+    let mut copied = 0;
+    while copied < file_size {
+        let addr = start + copied;
+        let source = if identity {
+            addr
+        } else {
+            arch::translate(addr).ok_or(LoadError::Corrupt)?
+        };
+        let n = (arch::PAGE_SIZE - addr % arch::PAGE_SIZE).min(file_size - copied);
+        unsafe {
+            core::ptr::copy_nonoverlapping(source as *const u8, (block + copied) as *mut u8, n)
+        };
+        copied += n;
+    }
+    unsafe { *(thread_pointer as *mut usize) = thread_pointer };
+
+    Ok(Tls {
+        start,
+        file_size,
+        mem_size,
+        align,
+        thread_pointer,
+    })
+}
+
+// Map a writable stack of at least the given size below the kernel image, with
+// an unmapped guard page between them. Return its top.
+fn map_stack(image_start: usize, size: usize, executable: bool) -> Option<usize> {
+    let n_pages = size.checked_add(arch::PAGE_SIZE - 1)? / arch::PAGE_SIZE;
+    let top = image_start.checked_sub(arch::PAGE_SIZE)?;
+    let bottom = top.checked_sub(n_pages.checked_mul(arch::PAGE_SIZE)?)?;
+
+    // The stack and its guard page must be in the free higher half.
+    if (bottom as u64) < validate::HIGHER_HALF {
+        return None;
+    }
+    if (0..=n_pages).any(|x| arch::translate(bottom + x * arch::PAGE_SIZE).is_some()) {
+        return None;
+    }
+
+    let frames = env::allocate_pages(n_pages)?;
+    for x in 0..n_pages {
+        let p_offset = x * arch::PAGE_SIZE;
+        arch::map(frames + p_offset, bottom + p_offset, true, executable);
     }
 
-    (rdfile_start_page, rdfile_len)
+    Some(top)
+}
+
+// Allocate a stack of at least the given size for an identity-mapped kernel,
+// which reaches it through the firmware's identity map. Return its top.
+fn allocate_stack(size: usize) -> Option<usize> {
+    let n_pages = size.checked_add(arch::PAGE_SIZE - 1)? / arch::PAGE_SIZE;
+    let frames = env::allocate_pages(n_pages)?;
+    Some(frames + n_pages * arch::PAGE_SIZE)
+}
+
+// Make the pages in the kernel's RELRO range read-only, as a dynamic linker
+// does after relocation. Like glibc, the end of the range is rounded down, so
+// that a partial last page shared with writable data stays writable.
+fn protect_relro(elf: &Elf64, base: u64) -> Result<(), LoadError> {
+    for segment in elf.program_headers().map_err(LoadError::NotElf64)? {
+        if segment.type_() == PHType::GnuRelro {
+            let start = segment.vaddr.wrapping_add(base) as usize;
+            let end = start.checked_add(segment.memsz as usize).ok_or(LoadError::Corrupt)?;
+            let mut page = start & !(arch::PAGE_SIZE - 1);
+            while page < end & !(arch::PAGE_SIZE - 1) {
+                let (_, executable) = arch::permissions(page).ok_or(LoadError::Corrupt)?;
+                arch::set_permissions(page, false, executable);
+                page += arch::PAGE_SIZE;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Give a page shared by two segments the permissions of both.
+fn share_page(page: usize, writable: bool, executable: bool) {
+    let (was_writable, was_executable) = arch::permissions(page).unwrap_or((false, false));
+    let writable = writable || was_writable;
+    let executable = executable || was_executable;
+    if writable && executable && !(was_writable && was_executable) {
+        println!("WARNING: kernel segments sharing the page at {:#x} make it writable and executable", page);
+    }
+    arch::set_permissions(page, writable, executable);
+}
+
+// Pick a random load base for a position-independent kernel, so that its image
+// lies in the randomization range and its segments keep their alignment.
+fn random_base(elf: &Elf64) -> Result<u64, LoadError> {
+    // Find the extent and the largest alignment of the loadable segments.
+    let mut low = u64::MAX;
+    let mut high = 0;
+    let mut align = KASLR_ALIGN;
+    for segment in elf.program_headers().map_err(LoadError::NotElf64)? {
+        if segment.type_() == PHType::Load {
+            low = low.min(segment.vaddr);
+            high = high.max(segment.vaddr + segment.memsz);
+            if segment.align.is_power_of_two() {
+                align = align.max(segment.align);
+            }
+        }
+    }
+    if low >= high {
+        return Err(LoadError::Corrupt);
+    }
+
+    // The base ranges from the one placing the image at the start of the range
+    // to the one placing its end at the end of the range.
+    let first = (KASLR_START.wrapping_sub(low)).wrapping_add(align - 1) & !(align - 1);
+    let last = KASLR_END.wrapping_sub(high) & !(align - 1);
+    if high - low > KASLR_END - KASLR_START || last.wrapping_sub(first) > KASLR_END - KASLR_START {
+        println!("WARNING: kernel is too large to randomize, loading it at the default base");
+        return Ok(DEFAULT_BASE);
+    }
+
+    let slots = (last.wrapping_sub(first)) / align + 1;
+    Ok(first.wrapping_add(rng::random_u64() % slots * align))
+}
+
+// Load a ramdisk into memory from a file, return its start address and length.
+// An empty ramdisk file counts as no ramdisk.
+pub fn load_ramdisk(rdfile: *mut file::Protocol) -> Result<Option<(usize, usize)>, LoadError> {
+    let (rdfile_start_page, rdfile_len) = env::read_file(rdfile).ok_or(LoadError::Read)?;
+    if rdfile_len == 0 {
+        println!("NOTE: ramdisk file is empty, booting without a ramdisk");
+        env::free_file(rdfile_start_page, rdfile_len);
+        return Ok(None);
+    }
+
+    Ok(Some((rdfile_start_page, rdfile_len)))
+}
+
+// Load a boot module into memory from a file, return its start address and length.
+pub fn load_module(mfile: *mut file::Protocol) -> Result<(usize, usize), LoadError> {
+    env::read_file(mfile).ok_or(LoadError::Read)
 }
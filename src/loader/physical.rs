@@ -0,0 +1,99 @@
+// Loading of kernel segments at fixed physical addresses
+//
+// 32-bit and identity-mapped kernels run at the physical addresses they are
+// linked for, so their segments are loaded at the exact addresses given by
+// their program headers instead of being mapped. The pages are allocated from
+// the firmware, which fails if they are in use, and are freed if loading fails
+// so that another kernel can use the same addresses.
+
+use super::LoadError;
+use crate::{arch, env};
+
+// The maximum number of allocations for a kernel: its loadable segments and
+// the memory the loader sets up for it.
+const MAX_ALLOCATIONS: usize = 32;
+
+// The pages allocated for a kernel loaded at physical addresses.
+pub struct PhysicalImage {
+    ranges: [(usize, usize); MAX_ALLOCATIONS],
+    count: usize,
+    // The end of the last segment, and of the pages allocated for it.
+    last_end: u64,
+    allocated_end: u64,
+}
+
+impl PhysicalImage {
+    pub fn new() -> PhysicalImage {
+        PhysicalImage {
+            ranges: [(0, 0); MAX_ALLOCATIONS],
+            count: 0,
+            last_end: 0,
+            allocated_end: 0,
+        }
+    }
+
+    // Load a segment at a physical address: allocate its pages there, copy its
+    // contents and zero the rest of its memory.
+    pub fn load_segment(
+        &mut self,
+        start: u64,
+        mem_size: u64,
+        data: &[u8],
+        executable: bool,
+    ) -> Result<(), LoadError> {
+        let end = start.checked_add(mem_size).ok_or(LoadError::Corrupt)?;
+        if data.len() as u64 > mem_size {
+            return Err(LoadError::Corrupt);
+        }
+
+        // Segments must be sorted and may only share a page, which is already
+        // allocated for the previous segment.
+        if start < self.last_end {
+            return Err(LoadError::Corrupt);
+        }
+        let page_mask = arch::PAGE_SIZE as u64 - 1;
+        let first_page = (start & !page_mask).max(self.allocated_end);
+        let end_page = end.checked_add(page_mask).ok_or(LoadError::Corrupt)? & !page_mask;
+        if first_page < end_page {
+            let n_pages = ((end_page - first_page) / arch::PAGE_SIZE as u64) as usize;
+            let frames = env::allocate_pages_at(first_page as usize, n_pages, executable)
+                .ok_or(LoadError::AddressInUse(first_page))?;
+            self.push(frames, n_pages)?;
+            unsafe { core::ptr::write_bytes(frames as *mut u8, 0, n_pages * arch::PAGE_SIZE) };
+            self.allocated_end = end_page;
+        }
+        self.last_end = end;
+
+        // The rest of the segment is zero from allocating its pages.
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), start as *mut u8, data.len());
+        }
+
+        Ok(())
+    }
+
+    // Allocate pages below 4 GiB for the kernel, return their address.
+    pub fn allocate_low(&mut self, n_pages: usize, executable: bool) -> Result<usize, LoadError> {
+        let pages = env::allocate_low_pages(n_pages, executable).ok_or(LoadError::OutOfMemory)?;
+        self.push(pages, n_pages)?;
+        Ok(pages)
+    }
+
+    // Record an allocation of pages.
+    fn push(&mut self, start: usize, n_pages: usize) -> Result<(), LoadError> {
+        if self.count == MAX_ALLOCATIONS {
+            env::free_pages(start, n_pages);
+            return Err(LoadError::Corrupt);
+        }
+        self.ranges[self.count] = (start, n_pages);
+        self.count += 1;
+        Ok(())
+    }
+
+    // Free all the recorded allocations.
+    pub fn free(&self) {
+        for &(start, n_pages) in &self.ranges[..self.count] {
+            env::free_pages(start, n_pages);
+        }
+    }
+}
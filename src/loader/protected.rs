@@ -0,0 +1,77 @@
+// Loader for 32-bit kernels
+//
+// 32-bit kernels are loaded at the physical addresses given by their program
+// headers and entered in protected mode with paging off, through a trampoline
+// below 4 GiB that leaves long mode. They get a stack below 4 GiB as well.
+
+use super::physical::PhysicalImage;
+use super::requests::Requests;
+use super::{validate, Kernel, LoadError};
+use crate::arch;
+use elf64::elf32::{Elf32, ElfAbi, ElfType, PHType};
+
+// The size of the stack 32-bit kernels are entered on.
+const STACK_SIZE: usize = 0x10000;
+
+// Load a 32-bit kernel from the contents of its file.
+pub fn load_kernel32(slice: &[u8]) -> Result<Kernel, LoadError> {
+    let elf = Elf32::from_slice(slice).map_err(LoadError::NotElf32)?;
+
+    // Check some ELF header fields to see if uefi-boot can load it.
+    if !elf.is_valid_locally() {
+        return Err(LoadError::WrongMachine);
+    }
+    if elf.abi() != ElfAbi::None || elf.abi_version() != 0 {
+        return Err(LoadError::UnsupportedAbi);
+    }
+    if elf.file_type() != ElfType::Executable {
+        return Err(LoadError::NotExecutable);
+    }
+
+    // Reject bad layouts before any page is allocated.
+    validate::validate_kernel32(&elf)?;
+
+    let mut image = PhysicalImage::new();
+    let result = load_segments(&elf, &mut image);
+    if result.is_err() {
+        image.free();
+    }
+
+    result
+}
+
+// Load the segments of a 32-bit kernel, then prepare its trampoline and stack.
+// The pages allocated for them move to the returned kernel.
+fn load_segments(elf: &Elf32, image: &mut PhysicalImage) -> Result<Kernel, LoadError> {
+    for segment in elf.program_headers().map_err(LoadError::NotElf32)? {
+        if segment.type_() != PHType::Load || segment.memsz == 0 {
+            continue;
+        }
+        let data = elf.segment_data(segment).map_err(LoadError::NotElf32)?;
+        let start = segment.paddr as u64;
+        let executable = segment.permissions().is_executable();
+        image.load_segment(start, segment.memsz as u64, data, executable)?;
+    }
+
+    // Copy the trampoline below 4 GiB and allocate a stack there.
+    let code = arch::protected_mode_trampoline();
+    let n_pages = code.len().div_ceil(arch::PAGE_SIZE);
+    let trampoline = image.allocate_low(n_pages, true)?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(code.as_ptr(), trampoline as *mut u8, code.len());
+    }
+    let n_pages = STACK_SIZE / arch::PAGE_SIZE;
+    let stack = image.allocate_low(n_pages, false)?;
+
+    Ok(Kernel {
+        entry: elf.entry() as usize,
+        slide: 0,
+        sections: None,
+        requests: Requests::none(),
+        stack_top: Some(stack + STACK_SIZE),
+        tls: None,
+        trampoline: Some(trampoline),
+        x86_features: 0,
+        image: core::mem::replace(image, PhysicalImage::new()),
+    })
+}
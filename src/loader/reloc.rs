@@ -0,0 +1,83 @@
+// Relocation of position-independent kernels
+
+use elf64::{Elf64, SymbolBinding, SHN_ABS};
+use super::LoadError;
+use crate::arch;
+
+// x86_64 relocation types.
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_64: u32 = 1;
+const R_X86_64_GLOB_DAT: u32 = 6;
+const R_X86_64_RELATIVE: u32 = 8;
+
+// Apply the dynamic relocations of a mapped kernel loaded at a base address.
+pub fn relocate(elf: &Elf64, base: u64) -> Result<(), LoadError> {
+    for rela in elf.rela_entries().map_err(LoadError::BadDynamic)? {
+        let value = match rela.type_() {
+            R_X86_64_NONE => continue,
+            R_X86_64_RELATIVE => base.wrapping_add(rela.addend as u64),
+            R_X86_64_64 => symbol_value(elf, rela.sym(), base)?.wrapping_add(rela.addend as u64),
+            R_X86_64_GLOB_DAT => symbol_value(elf, rela.sym(), base)?,
+            t => return Err(LoadError::UnsupportedRelocation(t)),
+        };
+        write_u64(base.wrapping_add(rela.offset), value)?;
+    }
+
+    Ok(())
+}
+
+// Check that the relocations of a kernel loaded at a base address can be
+// applied, before it is mapped: their types must be supported, their symbols
+// defined and their targets inside the image, as told by `in_image`.
+pub fn check(elf: &Elf64, base: u64, in_image: impl Fn(u64) -> bool) -> Result<(), LoadError> {
+    for rela in elf.rela_entries().map_err(LoadError::BadDynamic)? {
+        match rela.type_() {
+            R_X86_64_NONE => continue,
+            R_X86_64_RELATIVE => {}
+            R_X86_64_64 | R_X86_64_GLOB_DAT => {
+                symbol_value(elf, rela.sym(), base)?;
+            }
+            t => return Err(LoadError::UnsupportedRelocation(t)),
+        }
+        let addr = base.wrapping_add(rela.offset);
+        if !in_image(addr) {
+            return Err(LoadError::RelocationOutOfRange(addr));
+        }
+    }
+
+    Ok(())
+}
+
+// Get the relocated value of a dynamic symbol.
+fn symbol_value(elf: &Elf64, index: u32, base: u64) -> Result<u64, LoadError> {
+    if index == 0 {
+        return Ok(0);
+    }
+
+    let symbol = elf.dynamic_symbol(index).map_err(LoadError::BadDynamic)?;
+    if symbol.is_undefined() {
+        // There is nothing to link against, so only weak references may be undefined.
+        if symbol.binding() == SymbolBinding::Weak {
+            Ok(0)
+        } else {
+            Err(LoadError::UndefinedSymbol(index))
+        }
+    } else if symbol.shndx == SHN_ABS {
+        Ok(symbol.value)
+    } else {
+        Ok(base.wrapping_add(symbol.value))
+    }
+}
+
+// Write a value to a mapped kernel address through its physical address, so
+// that page permissions and frames split across pages don't matter.
+fn write_u64(addr: u64, value: u64) -> Result<(), LoadError> {
+    for (x, byte) in value.to_le_bytes().iter().enumerate() {
+        let paddr = arch::translate(addr as usize + x).ok_or(LoadError::RelocationOutOfRange(addr))?;
+        unsafe {
+            *(paddr as *mut u8) = *byte;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// Boot requests embedded in the kernel
+//
+// A kernel states what it needs from the loader in ELF notes owned by
+// `uefi-boot`, kept in its note segments or, if it has none, in a
+// `.note.uefi-boot` section. The note types and their contents are defined in
+// the interface. Requests that cannot be honored are reported to the kernel.
+
+use elf64::{Elf64, Elf64Error, PHType};
+use crate::interface::{
+    NOTE_DIRECT_MAP, NOTE_FRAMEBUFFER, NOTE_IDENTITY_MAP, NOTE_OWNER, NOTE_STACK_SIZE,
+};
+
+// The requests of a kernel.
+pub struct Requests {
+    // The minimum framebuffer resolution (horizontal, vertical).
+    pub min_resolution: Option<(u32, u32)>,
+    // The size of the stack to enter the kernel on, in bytes.
+    pub stack_size: Option<usize>,
+    // The note types of requests that cannot be honored, as a bit mask.
+    pub unsupported: u64,
+}
+
+impl Requests {
+    // No requests.
+    pub fn none() -> Requests {
+        Requests {
+            min_resolution: None,
+            stack_size: None,
+            unsupported: 0,
+        }
+    }
+
+    // Report a request as unsupported.
+    pub fn unsupported(&mut self, type_: u32) {
+        if type_ < 64 {
+            self.unsupported |= 1 << type_;
+        }
+    }
+}
+
+// Collect the boot requests from the notes of a kernel.
+pub fn parse_requests(elf: &Elf64) -> Result<Requests, Elf64Error> {
+    let mut requests = Requests::none();
+
+    let mut found_segment = false;
+    for segment in elf.program_headers()? {
+        if segment.type_() == PHType::Note {
+            found_segment = true;
+            for note in elf.segment_notes(segment)? {
+                if note.name == NOTE_OWNER.as_bytes() {
+                    parse_request(&mut requests, note.type_, note.desc);
+                }
+            }
+        }
+    }
+
+    // Section headers are optional, so a kernel whose sections cannot be
+    // searched just has no requests.
+    if !found_segment {
+        let section = match elf.section_by_name(".note.uefi-boot") {
+            Ok(section) => section,
+            Err(e) => {
+                println!("WARNING: ignoring boot requests, the kernel's sections are malformed: {:?}", e);
+                None
+            }
+        };
+        if let Some(section) = section {
+            for note in elf.section_notes(section)? {
+                if note.name == NOTE_OWNER.as_bytes() {
+                    parse_request(&mut requests, note.type_, note.desc);
+                }
+            }
+        }
+    }
+
+    Ok(requests)
+}
+
+// Record a request, or report it as unsupported if it is unknown or malformed.
+fn parse_request(requests: &mut Requests, type_: u32, desc: &[u8]) {
+    let u32_at = |x: usize| u32::from_le_bytes([desc[x], desc[x + 1], desc[x + 2], desc[x + 3]]);
+    let u64_at = |x: usize| (u32_at(x + 4) as u64) << 32 | u32_at(x) as u64;
+    match (type_, desc.len()) {
+        (NOTE_FRAMEBUFFER, 8) => requests.min_resolution = Some((u32_at(0), u32_at(4))),
+        (NOTE_STACK_SIZE, 8) if u64_at(0) != 0 => requests.stack_size = Some(u64_at(0) as usize),
+        // The identity map is always kept.
+        (NOTE_IDENTITY_MAP, 4) if u32_at(0) != 0 => {}
+        (NOTE_DIRECT_MAP, 8) => {
+            println!("WARNING: the kernel requests a direct map, which is not supported");
+            requests.unsupported(type_);
+        }
+        _ => {
+            println!("WARNING: unsupported or malformed boot request of type {}", type_);
+            requests.unsupported(type_);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// Copies of the kernel's section headers and symbol table
+//
+// Like multiboot's ELF sections tag, the kernel gets a copy of its section
+// header table. The symbol table, its string table and the section name string
+// table are not loaded by the program headers, so they are copied into memory
+// too and the `addr` field of their headers is set to the physical address of
+// the copy. Other sections keep their link address, or zero if not loaded.
+
+use elf64::{Elf64, Elf64Error, SectionHeader, SHType};
+use crate::env;
+use core::mem::size_of;
+
+// A copy of the kernel's section header table.
+pub struct Sections {
+    // The start of the table.
+    pub start: usize,
+    // The number of entries in the table.
+    pub count: usize,
+    // The size of each entry.
+    pub entry_size: usize,
+    // The index of the section holding the section names.
+    pub names_index: usize,
+}
+
+// Copy the section header table of a kernel and the sections needed to look
+// up symbols by address. Returns None if the kernel has no section headers.
+pub fn copy_sections(elf: &Elf64) -> Result<Option<Sections>, Elf64Error> {
+    let count = elf.section_count() as usize;
+    if count == 0 {
+        return Ok(None);
+    }
+
+    // The string table of the symbol table, if the kernel is not stripped.
+    let strings_index = elf.symbol_table()?.map(|table| table.link as usize);
+    let names_index = elf.section_names_index() as usize;
+
+    let start = env::allocate_pool(count * size_of::<SectionHeader>())
+        .expect("failed to allocate buffer for the section headers");
+    let table = unsafe { core::slice::from_raw_parts_mut(start as *mut SectionHeader, count) };
+
+    for (x, section) in elf.section_headers()?.enumerate() {
+        let copy = &mut table[x];
+        unsafe {
+            core::ptr::copy_nonoverlapping(section as *const SectionHeader, copy as *mut _, 1);
+        }
+
+        let wanted = section.type_() == SHType::SymTab
+            || Some(x) == strings_index
+            || x == names_index;
+        if wanted && section.addr == 0 {
+            let data = elf.section_data(section)?;
+            if !data.is_empty() {
+                let buffer = env::allocate_pool(data.len())
+                    .expect("failed to allocate buffer for a kernel section");
+                unsafe {
+                    core::ptr::copy_nonoverlapping(data.as_ptr(), buffer as *mut u8, data.len());
+                }
+                copy.addr = buffer as u64;
+            }
+        }
+    }
+
+    Ok(Some(Sections {
+        start,
+        count,
+        entry_size: size_of::<SectionHeader>(),
+        names_index,
+    }))
+}
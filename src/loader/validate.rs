@@ -0,0 +1,167 @@
+// Validation of a kernel's layout before it is mapped
+//
+// The ELF parser checks that the kernel file is well formed. This pass checks
+// that the kernel can be loaded where it asks to be: its loadable segments
+// must be in the higher half, or at their physical addresses in the lower half
+// for identity-mapped kernels, sorted and not overlapping. Its entry point must
+// be in an executable segment, and its relocations must be supported and fall
+// inside the image. Its TLS segment, if any, must have a valid alignment and a
+// template inside the image. 32-bit kernels get the same checks for their
+// physical addresses, which must be below 4 GiB. A bad layout is then reported
+// before any page table is modified or any page is allocated, instead of
+// halfway through loading the kernel.
+
+use super::{reloc, LoadError};
+use crate::arch;
+use elf64::elf32::{self, Elf32};
+use elf64::{Elf64, ElfType, PHType, ProgramHeader};
+
+// The start of the higher half, where kernels are mapped.
+pub const HIGHER_HALF: u64 = 0xffff800000000000;
+
+// The first address 32-bit kernels cannot reach.
+const LIMIT_32: u64 = 0x100000000;
+
+// Check whether a kernel runs identity-mapped. Executables linked in the lower
+// half run at their physical addresses, using the firmware's identity map,
+// instead of being mapped in the higher half.
+pub fn is_identity_mapped(elf: &Elf64) -> Result<bool, LoadError> {
+    let first_segment = elf
+        .program_headers()
+        .map_err(LoadError::NotElf64)?
+        .find(|s| s.type_() == PHType::Load && s.memsz != 0);
+    Ok(elf.file_type() == ElfType::Executable
+        && first_segment.is_some_and(|s| s.vaddr < HIGHER_HALF))
+}
+
+// Check the layout of a kernel that will be loaded at a base address, or
+// identity-mapped.
+pub fn validate_kernel(elf: &Elf64, base: u64, identity: bool) -> Result<(), LoadError> {
+    // The end of the previous segment.
+    let mut last_end = None;
+
+    for segment in elf.program_headers().map_err(LoadError::NotElf64)? {
+        if segment.type_() != PHType::Load {
+            continue;
+        }
+
+        // The segment may start anywhere in a page, but its address and file
+        // offset must be at the same position in their pages.
+        let start = segment.vaddr.wrapping_add(base);
+        if !arch::check_page_alignment((start ^ segment.offset) as usize) {
+            return Err(LoadError::MisalignedSegment(start));
+        }
+        if segment.memsz == 0 {
+            continue;
+        }
+
+        // The segment must not wrap around the end of the address space.
+        // Identity-mapped segments must be linked at their physical addresses.
+        let end = match start.checked_add(segment.memsz) {
+            Some(end) if identity && segment.paddr == start && end <= HIGHER_HALF => end,
+            Some(end) if !identity && start >= HIGHER_HALF => end,
+            _ if identity => return Err(LoadError::NotIdentityMapped(start)),
+            _ => return Err(LoadError::LowerHalfSegment(start)),
+        };
+
+        // Segments must be sorted and only share a page, not addresses.
+        if last_end.is_some_and(|last_end| start < last_end) {
+            return Err(LoadError::OverlappingSegments(start));
+        }
+        last_end = Some(end);
+    }
+    if last_end.is_none() {
+        return Err(LoadError::NoSegments);
+    }
+
+    // A TLS block is aligned like the TLS segment, at most to a page.
+    let tls_segment = elf
+        .program_headers()
+        .map_err(LoadError::NotElf64)?
+        .find(|s| s.type_() == PHType::ThreadLocalStorage);
+    let bad_align = |s: &&ProgramHeader| {
+        s.align != 0 && (!s.align.is_power_of_two() || s.align > arch::PAGE_SIZE as u64)
+    };
+    if let Some(s) = tls_segment.filter(bad_align) {
+        return Err(LoadError::BadTlsAlignment(s.align));
+    }
+
+    // The TLS template is copied from the loaded image, so it must be in it.
+    let template_in_image = |s: &ProgramHeader| {
+        s.filesz == 0 || segment_at(elf, base, s.vaddr.wrapping_add(base), s.filesz).is_some()
+    };
+    if !tls_segment.is_none_or(template_in_image) {
+        return Err(LoadError::Corrupt);
+    }
+
+    // The entry point must be in executable memory.
+    let entry = elf.entry().wrapping_add(base);
+    let segment = segment_at(elf, base, entry, 1);
+    if !segment.is_some_and(|s| s.permissions().is_executable()) {
+        return Err(LoadError::BadEntry(entry));
+    }
+
+    // Relocations are only applied to position-independent kernels.
+    if elf.file_type() == ElfType::SharedObject {
+        reloc::check(elf, base, |addr| segment_at(elf, base, addr, 8).is_some())?;
+    }
+
+    Ok(())
+}
+
+// Find the loadable segment holding a range of addresses of a kernel loaded at
+// a base address.
+fn segment_at<'a>(elf: &'a Elf64, base: u64, addr: u64, size: u64) -> Option<&'a ProgramHeader> {
+    elf.program_headers().ok()?.find(|s| {
+        let start = s.vaddr.wrapping_add(base);
+        s.type_() == PHType::Load
+            && addr >= start
+            && addr.checked_add(size).is_some_and(|end| end <= start + s.memsz)
+    })
+}
+
+// Check the layout of a 32-bit kernel, which is loaded at the physical
+// addresses of its segments and entered with paging off.
+pub fn validate_kernel32(elf: &Elf32) -> Result<(), LoadError> {
+    let segments = || elf.program_headers().map_err(LoadError::NotElf32);
+
+    // The end of the previous segment.
+    let mut last_end = None;
+
+    for segment in segments()? {
+        if segment.type_() != PHType::Load || segment.memsz == 0 {
+            continue;
+        }
+
+        // The segment must be reachable in protected mode.
+        let start = segment.paddr as u64;
+        let end = start + segment.memsz as u64;
+        if end > LIMIT_32 {
+            return Err(LoadError::SegmentAbove4GiB(start));
+        }
+
+        // Segments must be sorted and only share a page, not addresses.
+        if last_end.is_some_and(|last_end| start < last_end) {
+            return Err(LoadError::OverlappingSegments(start));
+        }
+        last_end = Some(end);
+    }
+    if last_end.is_none() {
+        return Err(LoadError::NoSegments);
+    }
+
+    // With paging off, the entry point is a physical address, which must be in
+    // executable memory.
+    let entry = elf.entry() as u64;
+    let in_segment = |s: &elf32::ProgramHeader| {
+        s.type_() == PHType::Load
+            && s.permissions().is_executable()
+            && entry >= s.paddr as u64
+            && entry < s.paddr as u64 + s.memsz as u64
+    };
+    if !segments()?.any(in_segment) {
+        return Err(LoadError::BadEntry(entry));
+    }
+
+    Ok(())
+}
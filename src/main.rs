@@ -13,22 +13,24 @@ mod env;
 #[path = "arch/x86_64.rs"]
 mod arch;
 
+mod config;
+mod editor;
 mod graphics;
 mod interface;
 mod loader;
+mod menu;
+mod rng;
+mod vars;
 
-use interface::BootInfo;
+use interface::{BootInfo, Module};
+use loader::LoadError;
 use r_efi::efi;
-use utf16_lit::utf16;
 
-// Static pointers to the UEFI system table and filesystem root.
+// Static pointers to the UEFI system table, loaded image and filesystem root.
 static mut ST: *const efi::SystemTable = 0 as *const _;
+static mut IMAGE: *mut efi::protocols::loaded_image::Protocol = 0 as *mut _;
 static mut ROOT: *mut efi::protocols::file::Protocol = 0 as *mut _;
 
-// Hard-coded paths to kernel and ramdisk.
-const KERNEL_PATH: &[u16] = &utf16!("uefi-boot\\kernel.elf64\0");
-const RAMDISK_PATH: &[u16] = &utf16!("uefi-boot\\init.rd\0");
-
 // Entry point, called by the EFI.
 #[export_name = "efi_main"]
 pub extern "C" fn main(image_handle: efi::Handle, st: *mut efi::SystemTable) {
@@ -41,34 +43,78 @@ pub extern "C" fn main(image_handle: efi::Handle, st: *mut efi::SystemTable) {
 
     env::init_fs(image_handle);
 
-    // If either the kernel or ramdisk is not present, panic.
-    let kfile =
-        env::open_file(KERNEL_PATH.as_ptr() as *mut _).expect("failed to open kernel executable");
-    let rdfile =
-        env::open_file(RAMDISK_PATH.as_ptr() as *mut _).expect("failed to open ramdisk file");
-
-    arch::prepare_root_pt();
+    let mut config = config::load();
+    if let Some((h_res, v_res)) = config.resolution {
+        graphics::set_resolution(h_res, v_res);
+    }
 
-    // Load the kernel and ramdisk into memory.
-    let entry_fn_ptr = loader::load_kernel(kfile);
-    let (rd_start, rd_length) = loader::load_ramdisk(rdfile);
+    // A one-shot entry requested by the OS is booted without showing the menu,
+    // though it can still be edited.
+    let oneshot = vars::take_oneshot_entry(config.entries());
+    let mut index = match oneshot {
+        Some(index) => {
+            menu::offer_edit(&mut config.entries_mut()[index]);
+            index
+        }
+        None => {
+            let default = vars::last_entry(config.entries()).unwrap_or(config.default);
+            let timeout = config.timeout;
+            menu::select(config.entries_mut(), default, timeout)
+        }
+    };
 
-    // Create the boot information structure.
-    let info_buffer = env::allocate_pool(core::mem::size_of::<BootInfo>())
+    // Create the boot information structure, below 4 GiB in case the kernel is 32-bit.
+    let info_pages = core::mem::size_of::<BootInfo>().div_ceil(arch::PAGE_SIZE);
+    let info_buffer = env::allocate_low_pages(info_pages, false)
         .expect("failed to allocate buffer for the boot information structure");
     let info = unsafe { &mut *(info_buffer as *mut BootInfo) };
-    info.ramdisk_start = rd_start;
-    info.ramdisk_length = rd_length;
-    info.efi_system_table = st as usize;
-    info.efi_gop_modes = graphics::get_mode();
+
+    // Load the selected entry, trying its fallbacks in turn if it fails.
+    let mut tried = 0u32;
+    let (entry, kernel) = loop {
+        let entry = &config.entries()[index];
+        tried |= 1 << index;
+        println!("booting {}", entry.name);
+
+        // An entry that has used up its boot tries is not loaded.
+        if vars::tries_left(entry) == Some(0) {
+            println!("ERROR: {} has no boot tries left", entry.name);
+        } else {
+            match load_entry(entry, info) {
+                Ok(kernel) => break (entry, kernel),
+                Err(e) => println!("ERROR: failed to load {}: {}", entry.name, e),
+            }
+        }
+
+        match entry.fallback {
+            Some(fallback) if tried & (1 << fallback) == 0 => {
+                println!("trying fallback entry {}", config.entries()[fallback].name);
+                index = fallback;
+            }
+            _ => panic!("no bootable entry left to try"),
+        }
+    };
+    info.efi_system_table = st as u64;
+    info.efi_gop_modes = graphics::get_mode().map(|mode| mode as u64).unwrap_or(0);
 
     println!("preparing kernel handoff...");
+    // A one-shot boot does not change the default entry.
+    if oneshot.is_none() {
+        vars::set_last_entry(entry);
+    }
+
+    // Use up one of the entry's boot tries.
+    if let Some(tries) = vars::tries_left(entry) {
+        vars::set_tries_left(entry, tries - 1);
+        println!("{} has {} boot tries left", entry.name, tries - 1);
+    }
 
     // Get the memory map.
-    let ((mmap, mmap_length, desc_size), mmap_key) = get_memory_map();
-    info.efi_mmap_start = mmap;
-    info.efi_mmap_length = mmap_length;
-    info.efi_mmap_desc_size = desc_size;
+    let low = kernel.trampoline.is_some();
+    let ((mmap, mmap_length, desc_size), mmap_key) = get_memory_map(low);
+    info.efi_mmap_start = mmap as u64;
+    info.efi_mmap_length = mmap_length as u64;
+    info.efi_mmap_desc_size = desc_size as u64;
 
     // Exit boot services.
     let status = unsafe { ((*(*ST).boot_services).exit_boot_services)(image_handle, mmap_key) };
@@ -76,13 +122,32 @@ pub extern "C" fn main(image_handle: efi::Handle, st: *mut efi::SystemTable) {
         panic!("failed to exit UEFI boot services");
     }
 
+    // Kernels are entered with control-flow enforcement off.
+    arch::disable_cet();
+
+    // Enter 32-bit kernels in protected mode.
+    if let Some(trampoline) = kernel.trampoline {
+        let stack_top = kernel.stack_top.expect("32-bit kernels have a stack");
+        arch::enter_protected_mode(trampoline, kernel.entry, stack_top, info_buffer);
+    }
+
+    // Point the FS segment at the boot processor's TLS block.
+    if info.tls_thread_pointer != 0 {
+        arch::set_thread_pointer(info.tls_thread_pointer as usize);
+    }
+
+    // Enter the kernel on the stack it requested, if any.
+    if let Some(stack_top) = kernel.stack_top {
+        arch::enter_kernel(kernel.entry, stack_top, interface::MAGIC, info_buffer);
+    }
+
     // Use sysv64 calling convention on x86_64.
     #[cfg(target_arch = "x86_64")]
     let entry: extern "sysv64" fn(magic: u64, info_ptr: usize);
 
     // Call the kernel's entry function.
     unsafe { 
-        entry = core::mem::transmute(entry_fn_ptr);
+        entry = core::mem::transmute(kernel.entry);
         entry(interface::MAGIC, info_buffer);
     }
 
@@ -90,8 +155,202 @@ pub extern "C" fn main(image_handle: efi::Handle, st: *mut efi::SystemTable) {
     loop {}
 }
 
+// Load the kernel, ramdisk, modules and command line of an entry, filling in
+// the boot information structure. Return the loaded kernel.
+fn load_entry(entry: &config::Entry, info: &mut BootInfo) -> Result<loader::Kernel, LoadError> {
+    // The kernel must be present, and so must a configured ramdisk. The
+    // default ramdisk is optional.
+    let kfile = env::open_path(entry.kernel).ok_or(LoadError::NotFound(entry.kernel))?;
+    let rdfile = match entry.ramdisk {
+        Some(path) => {
+            let rdfile = env::open_path(path);
+            if rdfile.is_none() {
+                if entry.ramdisk_required {
+                    return Err(LoadError::NotFound(path));
+                }
+                println!("WARNING: ramdisk {} not found, booting without a ramdisk", path);
+            }
+            rdfile
+        }
+        None => None,
+    };
+
+    arch::prepare_root_pt();
+
+    // Load the kernel and ramdisk into memory.
+    let base = entry.base.unwrap_or(loader::DEFAULT_BASE);
+    let mut kernel = loader::load_kernel(kfile, base, entry.kaslr)?;
+
+    // Pages the kernel was loaded at are freed if the rest of the entry fails
+    // to load, so that a fallback kernel can be loaded at the same addresses.
+    match load_boot_files(entry, rdfile, &mut kernel, info) {
+        Ok(()) => Ok(kernel),
+        Err(e) => {
+            kernel.image.free();
+            Err(e)
+        }
+    }
+}
+
+// Load the ramdisk, modules and command line of an entry for a loaded kernel,
+// filling in the boot information structure.
+fn load_boot_files(
+    entry: &config::Entry,
+    rdfile: Option<*mut efi::protocols::file::Protocol>,
+    kernel: &mut loader::Kernel,
+    info: &mut BootInfo,
+) -> Result<(), LoadError> {
+    // Honor the kernel's framebuffer request. The loader handles the others.
+    if let Some((h_res, v_res)) = kernel.requests.min_resolution {
+        if !graphics::set_min_resolution(h_res, v_res) {
+            kernel.requests.unsupported(interface::NOTE_FRAMEBUFFER);
+        }
+    }
+    let ramdisk = match rdfile {
+        Some(rdfile) => loader::load_ramdisk(rdfile)?,
+        None => None,
+    };
+    // 32-bit kernels cannot reach anything above 4 GiB.
+    let low = kernel.trampoline.is_some();
+    let ramdisk = ramdisk.map(|(start, length)| (lower(low, start, length), length));
+    let (modules_start, modules_count) = load_modules(entry.modules(), low)?;
+    let (cmdline_start, cmdline_length) = build_cmdline(entry.cmdline);
+    let cmdline_start = lower(low, cmdline_start, cmdline_length + 1);
+
+    info.kernel_slide = kernel.slide;
+    info.unsupported_requests = kernel.requests.unsupported;
+    info.x86_features = kernel.x86_features as u64;
+    info.tls_template_start = kernel.tls.as_ref().map(|t| t.start as u64).unwrap_or(0);
+    info.tls_template_file_size = kernel.tls.as_ref().map(|t| t.file_size as u64).unwrap_or(0);
+    info.tls_template_mem_size = kernel.tls.as_ref().map(|t| t.mem_size as u64).unwrap_or(0);
+    info.tls_template_align = kernel.tls.as_ref().map(|t| t.align as u64).unwrap_or(0);
+    info.tls_thread_pointer = kernel.tls.as_ref().map(|t| t.thread_pointer as u64).unwrap_or(0);
+    info.elf_sections_start = kernel.sections.as_ref().map(|s| s.start as u64).unwrap_or(0);
+    info.elf_sections_count = kernel.sections.as_ref().map(|s| s.count as u64).unwrap_or(0);
+    info.elf_section_size = kernel.sections.as_ref().map(|s| s.entry_size as u64).unwrap_or(0);
+    info.elf_section_names_index =
+        kernel.sections.as_ref().map(|s| s.names_index as u64).unwrap_or(0);
+    info.ramdisk_start = ramdisk.map(|(start, _)| start as u64).unwrap_or(0);
+    info.ramdisk_length = ramdisk.map(|(_, length)| length as u64).unwrap_or(0);
+    info.cmdline_start = cmdline_start as u64;
+    info.cmdline_length = cmdline_length as u64;
+    info.modules_start = modules_start as u64;
+    info.modules_count = modules_count as u64;
+
+    Ok(())
+}
+
+// Move a buffer below 4 GiB if `low` is set and it is not already there,
+// return its new start address. The original buffer is left in place.
+fn lower(low: bool, start: usize, length: usize) -> usize {
+    if !low || (start + length) as u64 <= 0x100000000 {
+        return start;
+    }
+
+    let n_pages = length.div_ceil(arch::PAGE_SIZE);
+    let copy = env::allocate_low_pages(n_pages.max(1), false)
+        .expect("failed to allocate buffer below 4 GiB");
+    unsafe {
+        core::ptr::copy_nonoverlapping(start as *const u8, copy as *mut u8, length);
+    }
+    copy
+}
+
+// Load the boot modules of an entry, return the address and length of the module
+// array. If `low` is set, the modules and the array are placed below 4 GiB.
+fn load_modules(modules: &[config::Module], low: bool) -> Result<(usize, usize), LoadError> {
+    let array_length = modules.len() * core::mem::size_of::<Module>();
+    let array_start = env::allocate_pool(array_length)
+        .expect("failed to allocate buffer for the module array");
+    let array_start = lower(low, array_start, array_length);
+    let array = unsafe { core::slice::from_raw_parts_mut(array_start as *mut Module, modules.len()) };
+
+    for (module, config) in array.iter_mut().zip(modules) {
+        let mfile = env::open_path(config.path).ok_or(LoadError::NotFound(config.path))?;
+        let (start, length) = loader::load_module(mfile)?;
+        let (cmdline_start, cmdline_length) = copy_str(config.cmdline);
+        module.start = lower(low, start, length) as u64;
+        module.length = length as u64;
+        module.cmdline_start = lower(low, cmdline_start, cmdline_length + 1) as u64;
+        module.cmdline_length = cmdline_length as u64;
+    }
+
+    Ok((array_start, modules.len()))
+}
+
+// Copy a string into a new NUL-terminated buffer, return its start address and
+// length (excluding the NUL terminator).
+fn copy_str(string: &str) -> (usize, usize) {
+    let start = env::allocate_pool(string.len() + 1).expect("failed to allocate buffer for a string");
+    let buffer = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, string.len() + 1) };
+    buffer[..string.len()].copy_from_slice(string.as_bytes());
+    buffer[string.len()] = 0;
+
+    (start, string.len())
+}
+
+// Build the kernel command line from the configured one followed by the image's
+// load options, return its start address and length (excluding the NUL terminator).
+fn build_cmdline(config_cmdline: &str) -> (usize, usize) {
+    let options = env::load_options();
+    let capacity = config_cmdline.len() + 1 + options.len() * 3 + 1;
+    let start = env::allocate_pool(capacity).expect("failed to allocate buffer for the command line");
+    let buffer = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, capacity) };
+
+    buffer[..config_cmdline.len()].copy_from_slice(config_cmdline.as_bytes());
+    let mut length = config_cmdline.len();
+
+    // Decode the load options after the configured command line. They are
+    // ignored unless they are text, since boot manager entries may pass binary data.
+    let options_start = length + 1;
+    let mut options_end = options_start;
+    let mut is_text = true;
+    for c in char::decode_utf16(options.iter().cloned()) {
+        match c {
+            Ok(c) if !c.is_control() || c.is_whitespace() => {
+                options_end += c.encode_utf8(&mut buffer[options_end..]).len();
+            }
+            _ => {
+                is_text = false;
+                break;
+            }
+        }
+    }
+
+    if is_text {
+        // Safe because only encoded characters were written to this range.
+        let text = unsafe { core::str::from_utf8_unchecked(&buffer[options_start..options_end]) };
+        let mut args = text.trim();
+
+        // The UEFI shell passes the image name as the first word.
+        if let Some(first) = args.split_whitespace().next() {
+            let first = first.as_bytes();
+            if first.len() >= 4 && first[first.len() - 4..].eq_ignore_ascii_case(b".efi") {
+                args = args[first.len()..].trim_start();
+            }
+        }
+
+        if !args.is_empty() {
+            let args_start = args.as_ptr() as usize - start;
+            let args_length = args.len();
+            if length != 0 {
+                buffer[length] = b' ';
+                length += 1;
+            }
+            buffer.copy_within(args_start..args_start + args_length, length);
+            length += args_length;
+        }
+    } else {
+        println!("WARNING: ignoring load options that are not text");
+    }
+    buffer[length] = 0;
+
+    (start, length)
+}
+
 // Get tuple (memory map pointer, memory map size, descriptor entry size, memory map key).
-pub fn get_memory_map() -> ((usize, usize, usize), usize) {
+// If `low` is set, the memory map is placed below 4 GiB.
+pub fn get_memory_map(low: bool) -> ((usize, usize, usize), usize) {
     // Call boot_services.get_memory_map() with a buffer of size 0.
     // mmap_size will then hold the required size of the buffer.
     let mut mmap_size = 0usize;
@@ -112,8 +371,12 @@ pub fn get_memory_map() -> ((usize, usize, usize), usize) {
     }
 
     // Retry with a buffer of the correct size (plus a buffer if the allocation alters the map).
-    let mmap_buffer =
-        env::allocate_pool(mmap_size + 128).expect("failed to allocate buffer for memory map");
+    let mmap_buffer = if low {
+        env::allocate_low_pages((mmap_size + 128).div_ceil(arch::PAGE_SIZE), false)
+    } else {
+        env::allocate_pool(mmap_size + 128)
+    };
+    let mmap_buffer = mmap_buffer.expect("failed to allocate buffer for memory map");
     let status = unsafe {
         ((*(*ST).boot_services).get_memory_map)(
             &mut mmap_size as *mut usize,
@@ -0,0 +1,147 @@
+// Interactive boot menu on the UEFI console
+
+use crate::config::Entry;
+use crate::{editor, env};
+
+// Scan codes for the keys used by the menu.
+const SCAN_UP: u16 = 0x01;
+const SCAN_DOWN: u16 = 0x02;
+const SCAN_HOME: u16 = 0x05;
+const SCAN_END: u16 = 0x06;
+
+// The watchdog timeout that the firmware's boot manager sets before starting a
+// boot option, in seconds. It is restored once the menu is done waiting.
+const WATCHDOG_TIMEOUT: usize = 300;
+
+// Characters produced by the enter key and the edit key.
+const CHAR_CARRIAGE_RETURN: u16 = 0x0d;
+const CHAR_EDIT: u16 = 'e' as u16;
+
+// Let the user pick a boot entry with the arrow keys, return its index. If a
+// timeout is given, the default entry is picked once it expires unless a key
+// is pressed first. Entries edited by the user are modified in place.
+pub fn select(entries: &mut [Entry], default: usize, timeout: Option<u64>) -> usize {
+    // There is nothing to choose from with a single entry, but it can still be
+    // edited.
+    if entries.len() == 1 || timeout == Some(0) {
+        let selected = if entries.len() == 1 { 0 } else { default };
+        offer_edit(&mut entries[selected]);
+        return selected;
+    }
+
+    // The menu may wait forever, so the firmware's watchdog must not reset the
+    // machine in the meantime.
+    env::set_watchdog_timer(0);
+
+    // Count down with a timer that fires every second.
+    let mut countdown = timeout;
+    let timer = match countdown {
+        Some(_) => env::create_seconds_timer(),
+        None => None,
+    };
+    if timer.is_none() {
+        countdown = None;
+    }
+
+    let mut selected = default;
+    loop {
+        draw(entries, selected, countdown);
+
+        let key = match (timer, countdown) {
+            (Some(timer), Some(seconds)) => match env::read_key_or(timer) {
+                Some(key) => {
+                    countdown = None;
+                    key
+                }
+                None if seconds <= 1 => break,
+                None => {
+                    countdown = Some(seconds - 1);
+                    continue;
+                }
+            },
+            _ => env::read_key(),
+        };
+        match (key.scan_code, key.unicode_char) {
+            (SCAN_UP, _) => selected = selected.checked_sub(1).unwrap_or(entries.len() - 1),
+            (SCAN_DOWN, _) => selected = (selected + 1) % entries.len(),
+            (SCAN_HOME, _) => selected = 0,
+            (SCAN_END, _) => selected = entries.len() - 1,
+            (_, CHAR_CARRIAGE_RETURN) => break,
+            (_, CHAR_EDIT) if edit(&mut entries[selected]) => break,
+            _ => {}
+        }
+    }
+
+    if let Some(timer) = timer {
+        env::close_event(timer);
+    }
+    env::set_watchdog_timer(WATCHDOG_TIMEOUT);
+    env::clear_screen();
+    selected
+}
+
+// Edit an entry booted without the menu if the edit key was pressed while the
+// loader started, without waiting for one. The entry is modified in place.
+pub fn offer_edit(entry: &mut Entry) {
+    while let Some(key) = env::poll_key() {
+        if key.unicode_char == CHAR_EDIT {
+            // The editor may wait forever, like the menu.
+            env::set_watchdog_timer(0);
+            edit(entry);
+            env::set_watchdog_timer(WATCHDOG_TIMEOUT);
+            break;
+        }
+    }
+}
+
+// Draw the menu with the selected entry highlighted.
+fn draw(entries: &[Entry], selected: usize, countdown: Option<u64>) {
+    env::clear_screen();
+    println!("uefi-boot: select an entry with the arrow keys and press enter");
+    println!("press e to edit the selected entry for this boot");
+    println!();
+
+    for (x, entry) in entries.iter().enumerate() {
+        if x == selected {
+            env::set_attribute(env::ATTR_HIGHLIGHT);
+            println!("  > {}", entry.name);
+            env::set_attribute(env::ATTR_NORMAL);
+        } else {
+            println!("    {}", entry.name);
+        }
+    }
+
+    if let Some(seconds) = countdown {
+        println!();
+        println!("booting {} in {}s, press any key to stop", entries[selected].name, seconds);
+    }
+}
+
+// Edit the command line and module paths of an entry. Returns false if the
+// user cancelled, leaving the entry unchanged.
+fn edit(entry: &mut Entry) -> bool {
+    env::clear_screen();
+    println!("editing {}: press enter to accept each line or escape to cancel", entry.name);
+    println!();
+
+    let mut edited = *entry;
+    edited.cmdline = match editor::edit("cmdline: ", entry.cmdline) {
+        Some(cmdline) => cmdline,
+        None => return false,
+    };
+    for module in edited.modules_mut() {
+        let path = match editor::edit("module: ", module.path) {
+            Some(path) => path,
+            None => return false,
+        };
+        // A module's string defaults to its path, so it follows the new path.
+        if module.cmdline == module.path {
+            module.cmdline = path;
+        }
+        module.path = path;
+    }
+
+    *entry = edited;
+    env::clear_screen();
+    true
+}
@@ -0,0 +1,63 @@
+// Entropy for kernel address space layout randomization
+//
+// Random numbers come from the firmware's EFI_RNG_PROTOCOL if it is available,
+// then from the processor's random number instructions, and finally from the
+// timestamp counter, which is only weakly unpredictable.
+
+use crate::{arch, ST};
+use r_efi::efi::protocols::rng;
+
+// Whether the weak entropy warning has been printed.
+static mut WARNED: bool = false;
+
+// Get a random 64-bit number.
+pub fn random_u64() -> u64 {
+    if let Some(value) = efi_random() {
+        return value;
+    }
+    if let Some(value) = arch::hardware_random() {
+        return value;
+    }
+
+    unsafe {
+        if !WARNED {
+            println!("WARNING: no random number source, using the timestamp counter");
+            WARNED = true;
+        }
+    }
+    mix(arch::timestamp())
+}
+
+// Get a random number from EFI_RNG_PROTOCOL.
+fn efi_random() -> Option<u64> {
+    let mut guid = rng::PROTOCOL_GUID;
+    let mut protocol = 0 as *mut rng::Protocol;
+    let status = unsafe {
+        ((*(*ST).boot_services).locate_protocol)(
+            &mut guid,
+            0 as *mut _,
+            &mut protocol as *mut _ as *mut *mut core::ffi::c_void,
+        )
+    };
+    if status.is_error() {
+        return None;
+    }
+
+    // A null algorithm selects the firmware's default.
+    let mut value = [0u8; 8];
+    let status = unsafe {
+        ((*protocol).get_rng)(protocol, 0 as *mut rng::Algorithm, value.len(), value.as_mut_ptr())
+    };
+    if status.is_error() {
+        None
+    } else {
+        Some(u64::from_le_bytes(value))
+    }
+}
+
+// Spread the bits of a weakly random value (the splitmix64 finalizer).
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,129 @@
+// Loader state kept in UEFI variables
+//
+// Variables use uefi-boot's vendor GUID and hold entry names as NUL-terminated
+// UTF-16 strings, so the running OS can read and write them.
+//
+// Entries with boot counting keep their remaining tries in a variable named
+// `LoaderTriesLeft-<entry name>` holding a little-endian u32. A missing
+// variable stands for the configured number of tries, so the OS marks a boot
+// as good by deleting it.
+
+use crate::config::Entry;
+use crate::env;
+use utf16_lit::utf16;
+
+// The variable holding the name of the last booted entry.
+const LAST_ENTRY: &[u16] = &utf16!("LoaderEntryLastBooted\0");
+
+// The variable holding the name of an entry to boot once.
+const ONESHOT_ENTRY: &[u16] = &utf16!("LoaderEntryOneShot\0");
+
+// The prefix of the variables holding the boot tries left for an entry.
+const TRIES_LEFT_PREFIX: &str = "LoaderTriesLeft-";
+
+// The maximum size of an entry name variable in bytes.
+const NAME_SIZE_MAX: usize = 256;
+
+// Get the index of the last booted entry, if it still exists.
+pub fn last_entry(entries: &[Entry]) -> Option<usize> {
+    find_entry(LAST_ENTRY, entries)
+}
+
+// Remember an entry as the last booted one.
+pub fn set_last_entry(entry: &Entry) {
+    set_entry(LAST_ENTRY, entry);
+}
+
+// Get the index of the entry to boot once, if one was requested, and delete the
+// request so that later boots go back to the default entry. The request is
+// deleted even if it cannot be read, so that a bad one is not retried forever.
+pub fn take_oneshot_entry(entries: &[Entry]) -> Option<usize> {
+    let mut buffer = [0u8; NAME_SIZE_MAX];
+    let size = env::get_variable(ONESHOT_ENTRY, &mut buffer);
+    env::set_variable(ONESHOT_ENTRY, &[]);
+    let size = size?;
+
+    let entry = entry_index(&buffer[..size], entries);
+    if entry.is_none() {
+        println!("WARNING: one-shot entry does not exist, ignoring it");
+    }
+    entry
+}
+
+// Get the number of boot tries an entry has left, or None if the entry does
+// not use boot counting.
+pub fn tries_left(entry: &Entry) -> Option<u32> {
+    let tries = entry.tries?;
+    let mut name = [0u16; NAME_SIZE_MAX / 2];
+    let name = tries_variable(entry, &mut name)?;
+
+    let mut buffer = [0u8; 4];
+    match env::get_variable(name, &mut buffer) {
+        Some(4) => Some(u32::from_le_bytes(buffer)),
+        _ => Some(tries),
+    }
+}
+
+// Store the number of boot tries an entry has left.
+pub fn set_tries_left(entry: &Entry, tries: u32) {
+    let mut name = [0u16; NAME_SIZE_MAX / 2];
+    if let Some(name) = tries_variable(entry, &mut name) {
+        env::set_variable(name, &tries.to_le_bytes());
+    }
+}
+
+// Build the NUL-terminated name of the tries left variable for an entry.
+fn tries_variable<'a>(entry: &Entry, buffer: &'a mut [u16]) -> Option<&'a [u16]> {
+    let mut n = 0;
+    for c in TRIES_LEFT_PREFIX.encode_utf16().chain(entry.name.encode_utf16()) {
+        if n + 1 >= buffer.len() {
+            println!("WARNING: entry name {} is too long for boot counting", entry.name);
+            return None;
+        }
+        buffer[n] = c;
+        n += 1;
+    }
+    buffer[n] = 0;
+    Some(&buffer[..n + 1])
+}
+
+// Get the index of the entry named by a variable.
+fn find_entry(variable: &[u16], entries: &[Entry]) -> Option<usize> {
+    let mut buffer = [0u8; NAME_SIZE_MAX];
+    let size = env::get_variable(variable, &mut buffer)?;
+    entry_index(&buffer[..size], entries)
+}
+
+// Get the index of the entry named by the contents of a variable.
+fn entry_index(data: &[u8], entries: &[Entry]) -> Option<usize> {
+    // Decode the little-endian UTF-16 name, up to the NUL terminator.
+    let name = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0);
+    let mut units = [0u16; NAME_SIZE_MAX / 2];
+    let mut n = 0;
+    for c in name {
+        units[n] = c;
+        n += 1;
+    }
+
+    entries
+        .iter()
+        .position(|e| e.name.encode_utf16().eq(units[..n].iter().cloned()))
+}
+
+// Store the name of an entry in a variable.
+fn set_entry(variable: &[u16], entry: &Entry) {
+    let mut buffer = [0u8; NAME_SIZE_MAX];
+    let mut size = 0;
+    for c in entry.name.encode_utf16().chain(core::iter::once(0)) {
+        if size + 2 > NAME_SIZE_MAX {
+            println!("WARNING: entry name {} is too long to store", entry.name);
+            return;
+        }
+        buffer[size..size + 2].copy_from_slice(&c.to_le_bytes());
+        size += 2;
+    }
+    env::set_variable(variable, &buffer[..size]);
+}